@@ -1,5 +1,8 @@
 // build.rs
 fn main() {
     println!("cargo:rustc-link-search=windows");
-    println!("cargo:rustc-link-lib=static=sqlite3");
+    // Not bundled into the library, the final binary links SQLite
+    println!("cargo:rustc-link-lib=static:-bundle=sqlite3");
+    // The migrations are embedded into the binary
+    println!("cargo:rerun-if-changed=migrations");
 }
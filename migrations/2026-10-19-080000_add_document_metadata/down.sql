@@ -0,0 +1,7 @@
+ALTER TABLE html_metadata DROP COLUMN service_name;
+ALTER TABLE html_metadata DROP COLUMN leli_version;
+ALTER TABLE html_metadata DROP COLUMN generated_at;
+ALTER TABLE html_metadata DROP COLUMN content_hash;
+ALTER TABLE html_metadata DROP COLUMN front_matter;
+ALTER TABLE html_metadata DROP COLUMN title;
+ALTER TABLE html_metadata DROP COLUMN source_path;
@@ -0,0 +1,7 @@
+ALTER TABLE html_metadata ADD COLUMN source_path TEXT;
+ALTER TABLE html_metadata ADD COLUMN title TEXT;
+ALTER TABLE html_metadata ADD COLUMN front_matter TEXT;
+ALTER TABLE html_metadata ADD COLUMN content_hash TEXT;
+ALTER TABLE html_metadata ADD COLUMN generated_at TIMESTAMP;
+ALTER TABLE html_metadata ADD COLUMN leli_version TEXT;
+ALTER TABLE html_metadata ADD COLUMN service_name TEXT;
@@ -0,0 +1 @@
+DROP INDEX html_metadata_file_path;
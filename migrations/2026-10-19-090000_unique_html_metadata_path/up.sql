@@ -0,0 +1,5 @@
+-- Keep only the latest row of paths saved more than once
+DELETE FROM html_metadata
+WHERE id NOT IN (SELECT MAX(id) FROM html_metadata GROUP BY file_path);
+
+CREATE UNIQUE INDEX html_metadata_file_path ON html_metadata (file_path);
@@ -0,0 +1,14 @@
+CREATE TABLE code_blocks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    document_id INTEGER NOT NULL REFERENCES html_metadata (id) ON DELETE CASCADE,
+    language TEXT,
+    target_file TEXT,
+    markdown_start_line INTEGER NOT NULL,
+    markdown_end_line INTEGER NOT NULL,
+    output_start_line INTEGER,
+    output_end_line INTEGER,
+    content_hash TEXT NOT NULL
+);
+
+CREATE INDEX code_blocks_document_id ON code_blocks (document_id);
+CREATE INDEX code_blocks_target_file ON code_blocks (target_file);
@@ -0,0 +1 @@
+DROP TABLE documents_fts;
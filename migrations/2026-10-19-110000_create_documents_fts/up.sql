@@ -0,0 +1,7 @@
+-- Full-text index of the saved documents; the rowid is the id of the document in html_metadata
+CREATE VIRTUAL TABLE documents_fts USING fts5 (
+    title,
+    headings,
+    prose,
+    code
+);
@@ -0,0 +1,12 @@
+-- References between documents; `target_path` is the page a link points to, or for a chunk
+-- reference the page of the document whose code blocks generate the referenced file
+CREATE TABLE doc_links (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source_id INTEGER NOT NULL REFERENCES html_metadata (id) ON DELETE CASCADE,
+    target_path TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    label TEXT
+);
+
+CREATE INDEX doc_links_source_id ON doc_links (source_id);
+CREATE INDEX doc_links_target_path ON doc_links (target_path);
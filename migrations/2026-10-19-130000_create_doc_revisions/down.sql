@@ -0,0 +1,2 @@
+DROP TABLE doc_revisions;
+DROP TABLE save_runs;
@@ -0,0 +1,21 @@
+-- One row per `save` run
+CREATE TABLE save_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TIMESTAMP NOT NULL,
+    leli_version TEXT,
+    document_count INTEGER NOT NULL
+);
+
+-- A document as saved by a run, recorded whenever its page or code differ from its last revision.
+-- Revisions refer to documents by path, so they outlive documents removed with `--prune`.
+CREATE TABLE doc_revisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES save_runs (id) ON DELETE CASCADE,
+    document_path TEXT NOT NULL,
+    content_hash TEXT,
+    size BIGINT NOT NULL,
+    generated_at TIMESTAMP,
+    code_hash TEXT NOT NULL
+);
+
+CREATE INDEX doc_revisions_document_path ON doc_revisions (document_path);
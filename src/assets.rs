@@ -0,0 +1,27 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The default stylesheet, compiled into the binary from `src/css/style.css`.
+pub const STYLE_CSS: &str = include_str!("css/style.css");
+/// Mermaid.js, compiled into the binary from `src/js/mermaid.min.js`.
+pub const MERMAID_JS: &str = include_str!("js/mermaid.min.js");
+/// Page layout of the native engine when no template is given. It only uses the variables
+/// `$title$`, `$css$` and `$body$`, so Pandoc accepts it as a template as well.
+pub const PAGE_TEMPLATE: &str = "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>$title$</title>\n\
+    <link rel=\"stylesheet\" href=\"$css$\">\n</head>\n<body>\n$body$</body>\n</html>\n";
+
+/// Location of the bundled stylesheet, relative to the doc output.
+pub const SHARED_CSS_PATH: &str = "css/style.css";
+
+/// Writes a bundled asset below `folder`, leaving the file alone if it is already up to date.
+pub fn write_asset(folder: &Path, relative_path: &str, content: &str) -> io::Result<()> {
+    let path = folder.join(relative_path);
+    if fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)
+}
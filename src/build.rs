@@ -0,0 +1,174 @@
+//! Tangling literate Rust from the `build.rs` of a crate:
+//!
+//! ```no_run
+//! // In `fn main` of build.rs
+//! leli::build::tangle("docs").expect("tangling the literate sources failed");
+//! ```
+//!
+//! The crate then includes the tangled files, named after `output_filename` of the front matter:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/billing.rs"));
+//! ```
+//!
+//! Rust has no `#line` directive, so compiler errors point into the tangled files in `OUT_DIR`
+//! and are not rewritten by leli. To find the markdown behind an error, look up the
+//! `// leli: <markdown file>:<line>` comment that starts every code block there, or ask
+//! [`TangledFile::markdown_line`] from your own tooling.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commands::extract::{collect_sources, Languages, LiterateDocument};
+use crate::error::{IoContext, LeliError};
+use crate::utils::FileFilter;
+
+/// Extension of the code blocks tangled for a build script.
+const RUST_EXTENSION: &str = "rs";
+
+/// A code block's place in a tangled file and in its markdown file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockLines {
+    /// First line of the code in the tangled file, counted from 1
+    pub output_start_line: usize,
+    pub line_count: usize,
+    pub markdown_path: PathBuf,
+    /// First line of the code in the markdown file, counted from 1
+    pub markdown_start_line: usize,
+}
+
+/// A Rust file written to `OUT_DIR`.
+#[derive(Debug, Clone, Default)]
+pub struct TangledFile {
+    pub path: PathBuf,
+    pub blocks: Vec<BlockLines>,
+    content: String,
+}
+
+impl TangledFile {
+    /// The markdown file and line a line of the tangled file was written from. Lines that do
+    /// not belong to a code block, such as the `// leli:` comments, have none.
+    pub fn markdown_line(&self, line: usize) -> Option<(&Path, usize)> {
+        self.blocks
+            .iter()
+            .find(|block| (block.output_start_line..block.output_start_line + block.line_count).contains(&line))
+            .map(|block| (block.markdown_path.as_path(), block.markdown_start_line + line - block.output_start_line))
+    }
+
+    fn push_block(&mut self, markdown_path: &Path, markdown_start_line: usize, code: &str) {
+        if self.content.is_empty() {
+            self.content.push_str("// Generated by leli, edit the literate markdown instead\n");
+        }
+        self.content.push_str(&format!("// leli: {}:{}\n", markdown_path.display(), markdown_start_line));
+        self.blocks.push(BlockLines {
+            output_start_line: self.content.lines().count() + 1,
+            line_count: code.lines().count(),
+            markdown_path: markdown_path.to_path_buf(),
+            markdown_start_line,
+        });
+        self.content.push_str(code);
+    }
+}
+
+/// Tangles the Rust code blocks of the markdown files below `folder` into `OUT_DIR`, keeping
+/// the subfolders, and tells Cargo to run the build script again when any of them changes.
+/// Files in `.leliignore` are skipped. Call it from `build.rs`; without `OUT_DIR` it fails.
+pub fn tangle(folder: impl AsRef<Path>) -> Result<Vec<TangledFile>, LeliError> {
+    let out_dir = env::var_os("OUT_DIR")
+        .ok_or_else(|| LeliError::Usage("OUT_DIR is not set, call leli::build::tangle from build.rs".to_string()))?;
+    tangle_into(folder.as_ref(), Path::new(&out_dir))
+}
+
+/// Tangles like [`tangle`], into the given folder instead of `OUT_DIR`.
+pub fn tangle_into(folder: &Path, output_folder: &Path) -> Result<Vec<TangledFile>, LeliError> {
+    let filter = FileFilter::new(folder, &[], &[], false)?;
+    let mut markdown_files = Vec::new();
+    collect_sources(folder, &filter, &mut markdown_files)?;
+    markdown_files.retain(|path| path.extension().and_then(|s| s.to_str()) == Some("md"));
+
+    // New documents only show up as a change of the folder
+    println!("cargo:rerun-if-changed={}", folder.display());
+    let mut files: Vec<TangledFile> = Vec::new();
+    for markdown_path in &markdown_files {
+        println!("cargo:rerun-if-changed={}", markdown_path.display());
+        let document = LiterateDocument::read(markdown_path, &Languages::default())?;
+        let Some(meta) = document.meta()? else {
+            continue;
+        };
+
+        let relative_folder = markdown_path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(folder).ok())
+            .unwrap_or(Path::new(""));
+        let path = output_folder
+            .join(relative_folder)
+            .join(format!("{}.{}", meta.output_filename, RUST_EXTENSION));
+        for block in document.code_blocks.iter().filter(|block| block.extension.as_deref() == Some(RUST_EXTENSION)) {
+            let index = match files.iter().position(|file| file.path == path) {
+                Some(index) => index,
+                None => {
+                    files.push(TangledFile { path: path.clone(), ..TangledFile::default() });
+                    files.len() - 1
+                }
+            };
+            files[index].push_block(markdown_path, block.start_line, &block.content);
+        }
+    }
+
+    for file in &files {
+        if let Some(parent) = file.path.parent() {
+            fs::create_dir_all(parent).at(parent)?;
+        }
+        // Rewriting unchanged files would make Cargo rebuild the crate every time
+        if fs::read_to_string(&file.path).ok().as_deref() != Some(file.content.as_str()) {
+            fs::write(&file.path, &file.content).at(&file.path)?;
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_of_two_documents_map_back_to_their_lines() {
+        let mut file = TangledFile::default();
+        file.push_block(Path::new("docs/a.md"), 5, "fn a() {}\nfn b() {}\n");
+        file.push_block(Path::new("docs/b.md"), 3, "fn c() {}\n");
+
+        let lines: Vec<&str> = file.content.lines().collect();
+        assert_eq!(lines[1], "// leli: docs/a.md:5");
+        assert_eq!(lines[2], "fn a() {}");
+        assert_eq!(lines[4], "// leli: docs/b.md:3");
+        assert_eq!(lines[5], "fn c() {}");
+
+        assert_eq!(file.markdown_line(1), None);
+        assert_eq!(file.markdown_line(3), Some((Path::new("docs/a.md"), 5)));
+        assert_eq!(file.markdown_line(4), Some((Path::new("docs/a.md"), 6)));
+        assert_eq!(file.markdown_line(5), None);
+        assert_eq!(file.markdown_line(6), Some((Path::new("docs/b.md"), 3)));
+        assert_eq!(file.markdown_line(7), None);
+    }
+
+    #[test]
+    fn documents_with_the_same_output_share_a_file() {
+        let root = env::temp_dir().join(format!("leli-build-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let docs = root.join("docs");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("a.md"), "---\noutput_filename: lib\n---\n\n```{.rust}\nfn a() {}\n```\n").unwrap();
+        fs::write(docs.join("b.md"), "---\noutput_filename: lib\n---\n```{.rust}\nfn b() {}\n```\n").unwrap();
+
+        let files = tangle_into(&docs, &root.join("out")).unwrap();
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert_eq!(file.path, root.join("out/lib.rs"));
+        let content = fs::read_to_string(&file.path).unwrap();
+        let line_of = |code: &str| content.lines().position(|line| line == code).unwrap() + 1;
+        assert_eq!(file.markdown_line(line_of("fn a() {}")), Some((docs.join("a.md").as_path(), 6)));
+        assert_eq!(file.markdown_line(line_of("fn b() {}")), Some((docs.join("b.md").as_path(), 5)));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! The command line of the `leli` binary.
+
+use clap::{Parser, Subcommand};
+
+use leli::commands::graph::GraphFormat;
+use leli::commands::query::{OutputFormat, QueryCommands};
+use leli::report::OutputMode;
+use leli::{Engine, MermaidMode};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Commands,
+    /// Report progress as text or as one JSON event per line
+    #[arg(long, value_enum, global = true, default_value_t = OutputMode::Text)]
+    pub output_format: OutputMode,
+    /// Only report errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Report more details, -vv for even more
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+/// Selects the files of the folder walked by `extract`, `translate`, `watch` and `serve`.
+#[derive(clap::Args, Debug)]
+pub struct FilterArgs {
+    /// Only process files matching this glob, relative to the folder; repeatable [default: `include` of leli.toml]
+    #[arg(long)]
+    pub include: Vec<String>,
+    /// Skip files matching this glob, relative to the folder; repeatable [default: `exclude` of leli.toml]
+    #[arg(long)]
+    pub exclude: Vec<String>,
+    /// Also skip the files ignored by `.gitignore`, besides those in `.leliignore`
+    #[arg(long)]
+    pub gitignore: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    Extract {
+        #[arg(short, long, conflicts_with = "folder")]
+        file: Option<String>,
+        #[arg(long, conflicts_with = "file")]
+        folder: Option<String>,
+        #[arg(short, long)]
+        output: Option<String>,
+        #[arg(short, long)]
+        protocol: Option<String>,
+        /// Number of files processed in parallel (defaults to the number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    Translate {
+        #[arg(short, long)]
+        folder: Option<String>,
+        /// Markdown to HTML converter [default: pandoc]
+        #[arg(short, long, value_enum)]
+        engine: Option<Engine>,
+        /// Code highlighting theme of the native engine: light, dark or a built-in theme name [default: light]
+        #[arg(short, long)]
+        theme: Option<String>,
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Stylesheet URL of the pages [default: a copy of the bundled stylesheet in the output]
+        #[arg(short, long)]
+        css: Option<String>,
+        /// Stylesheet file to copy into the output in place of the bundled one
+        #[arg(long, conflicts_with = "css")]
+        stylesheet: Option<String>,
+        /// Page template, see the Pandoc manual; the native engine fills in `$title$`, `$css$` and `$body$`
+        #[arg(long)]
+        template: Option<String>,
+        /// Mermaid.js file [default: the bundled copy]
+        #[arg(short, long)]
+        mermaid: Option<String>,
+        /// Inline Mermaid.js into every page or share one copy in the doc output [default: inline]
+        #[arg(long, value_enum)]
+        mermaid_mode: Option<MermaidMode>,
+        /// Render diagrams to inline SVG with this command, e.g. "mmdc -i {input} -o {output}"
+        #[arg(long)]
+        mermaid_renderer: Option<String>,
+        /// Number of Pandoc processes run in parallel (defaults to the number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Save the metadata of the generated pages to this database, as `save` does
+        #[arg(short, long)]
+        db: Option<String>,
+        /// Do not write `created_html_files.txt`
+        #[arg(long)]
+        no_file_list: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    Save {
+        #[arg(short, long)]
+        file: String,
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+        /// Remove documents from the database that are not listed in the file
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Full-text search over the documents stored by `save`
+    Search {
+        /// FTS5 query, e.g. `math`, `"simple math"` or `simple AND rust`
+        query: String,
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+        /// Maximum number of results
+        #[arg(short, long, default_value_t = 10)]
+        limit: i64,
+    },
+    /// Inspect the database written by `save`
+    Query {
+        #[command(subcommand)]
+        query: QueryCommands,
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+        #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Export the links between the saved documents as a graph
+    Graph {
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// Write the graph to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Show when a document and the code it generates changed across `save` runs
+    History {
+        /// Path of the HTML page or of its markdown file
+        document: String,
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Inspect and manage the schema of the database
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+        /// Database file [default: `path` in the [database] section of leli.toml]
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+    /// Extract and translate the documents of a folder again whenever they change
+    Watch {
+        /// Folder to watch [default: `input` of leli.toml]
+        #[arg(short, long)]
+        folder: Option<String>,
+        /// Only translate, do not extract code
+        #[arg(long, conflicts_with = "no_translate")]
+        no_extract: bool,
+        /// Only extract code, do not translate
+        #[arg(long)]
+        no_translate: bool,
+        /// Markdown to HTML converter [default: pandoc]
+        #[arg(short, long, value_enum)]
+        engine: Option<Engine>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Preview the translated documents in the browser, rebuilding and reloading them on change
+    Serve {
+        /// Folder to watch [default: `input` of leli.toml]
+        #[arg(short, long)]
+        folder: Option<String>,
+        /// Port on localhost to serve the documents on
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+        /// Only translate, do not extract code
+        #[arg(long)]
+        no_extract: bool,
+        /// Markdown to HTML converter [default: pandoc]
+        #[arg(short, long, value_enum)]
+        engine: Option<Engine>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Create a new literate project
+    Init {
+        /// Folder of the project
+        #[arg(default_value = ".")]
+        folder: String,
+        /// Lay the docs out as an AImM service with private and public folders
+        #[arg(short, long)]
+        service: Option<String>,
+        /// Copy the default stylesheet and page template into the project for customization
+        #[arg(long)]
+        assets: bool,
+    },
+    /// Work with the project configuration in leli.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Print the settings commands run with: leli.toml with the defaults filled in
+    Show,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommands {
+    /// List the migrations and whether they are applied
+    Status,
+    /// Apply the pending migrations
+    Migrate,
+    /// Delete all saved data by reverting and reapplying every migration
+    Reset {
+        /// Confirm that all saved data is deleted
+        #[arg(long)]
+        yes: bool,
+    },
+}
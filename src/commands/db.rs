@@ -0,0 +1,72 @@
+use std::error::Error;
+
+use diesel::migration::MigrationSource;
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+
+use crate::commands::save::MIGRATIONS;
+
+type MigrationResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A migration compiled into leli and whether the database has applied it.
+#[derive(Debug, Serialize)]
+pub struct MigrationState {
+    pub name: String,
+    pub applied: bool,
+}
+
+/// Lists every migration compiled into leli and whether the database has applied it.
+/// Diesel records applied migrations by version, so the schema state never has to be guessed
+/// from the tables that happen to exist.
+pub fn migration_status(conn: &mut SqliteConnection) -> MigrationResult<Vec<MigrationState>> {
+    let applied: Vec<String> = conn
+        .applied_migrations()?
+        .into_iter()
+        .map(|version| version.to_string())
+        .collect();
+    let migrations = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)?;
+    Ok(migrations
+        .iter()
+        .map(|migration| MigrationState {
+            name: migration.name().to_string(),
+            applied: applied.contains(&migration.name().version().to_string()),
+        })
+        .collect())
+}
+
+/// Formats the migration states for the console, ending with what to do next.
+pub fn format_migration_status(states: &[MigrationState]) -> String {
+    let mut text = String::new();
+    for state in states {
+        text.push_str(&format!("{:<8} {}\n", if state.applied { "applied" } else { "pending" }, state.name));
+    }
+    let pending = states.iter().filter(|state| !state.applied).count();
+    if pending == 0 {
+        text.push_str("Database is up to date\n");
+    } else {
+        text.push_str(&format!("{} of {} migrations pending, run `leli db migrate`\n", pending, states.len()));
+    }
+    text
+}
+
+/// Applies the pending migrations.
+pub fn migrate(conn: &mut SqliteConnection) -> MigrationResult<()> {
+    let applied = conn.run_pending_migrations(MIGRATIONS)?;
+    if applied.is_empty() {
+        log::info!("Database is up to date");
+    }
+    for version in applied {
+        log::info!("Applied database migration {}", version);
+    }
+    Ok(())
+}
+
+/// Reverts every migration and applies them again, which leaves an empty database.
+pub fn reset(conn: &mut SqliteConnection) -> MigrationResult<()> {
+    let reverted = conn.revert_all_migrations(MIGRATIONS)?;
+    log::info!("Reverted {} database migrations", reverted.len());
+    let applied = conn.run_pending_migrations(MIGRATIONS)?;
+    log::info!("Applied {} database migrations", applied.len());
+    Ok(())
+}
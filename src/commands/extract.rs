@@ -1,127 +1,471 @@
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+use crate::error::{IoContext, LeliError};
+use crate::report::{self, Event, SkipReason};
+use crate::utils::{default_jobs, report_failures, run_parallel, sorted_dir_entries, FileFilter};
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct MarkdownMeta {
     pub output_filename: String,
 }
 
-pub fn extract_code_from_markdown(file_path: &str) -> io::Result<Result<HashMap<String, String>, String>> {
-    let path = Path::new(file_path);
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
-
-    let mut meta_data = String::new();
-    let mut in_front_matter = false;
-    let mut found_meta = false;
-    let mut code_blocks: HashMap<String, String> = HashMap::new();
-    let mut current_lang = String::new();
-
-    for line in reader.lines() {
-        let line = line?;
-
-        if line.trim() == "---" && !in_front_matter {
-            in_front_matter = true;
-        } else if line.trim() == "---" && in_front_matter {
-            in_front_matter = false;
-            found_meta = true;
-        } else if in_front_matter {
-            meta_data.push_str(&line);
-            meta_data.push('\n');
-        } else if line.trim().starts_with("```") && !current_lang.is_empty() {
-            current_lang.clear();
-        } else if line.trim().starts_with("```") {
-            if line.contains(".python") {
-                current_lang = "python".to_string();
-            } else if line.contains(".rust") {
-                current_lang = "rust".to_string();
+/// Code block languages that are always extracted, with the extension of their files.
+const BUILTIN_LANGUAGES: &[(&str, &str)] = &[("python", "py"), ("rust", "rs")];
+
+/// The code block languages to extract, with the extension of their files: the built-in ones
+/// and, for example, those of the `[languages]` table of `leli.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Languages {
+    extensions: BTreeMap<String, String>,
+}
+
+impl Default for Languages {
+    /// Only the built-in languages.
+    fn default() -> Self {
+        Languages::new(BTreeMap::new())
+    }
+}
+
+impl Languages {
+    /// The built-in languages and `languages`, which may also change a built-in extension.
+    pub fn new(languages: BTreeMap<String, String>) -> Self {
+        let mut extensions: BTreeMap<String, String> = BUILTIN_LANGUAGES
+            .iter()
+            .map(|(language, extension)| (language.to_string(), extension.to_string()))
+            .collect();
+        extensions.extend(languages);
+        Languages { extensions }
+    }
+
+    /// Returns the file extension of the code extracted for a code block language.
+    pub fn extension(&self, language: &str) -> Option<&str> {
+        self.extensions.get(language).map(String::as_str)
+    }
+
+    /// Every language with its extension.
+    pub fn extensions(&self) -> &BTreeMap<String, String> {
+        &self.extensions
+    }
+}
+
+/// Parses front matter. Errors carry their line within `meta_data`, counted from 1.
+fn parse_meta(meta_data: &str) -> Result<MarkdownMeta, (serde_yaml::Error, Option<usize>)> {
+    let cleaned_meta_data = meta_data.trim_end_matches("---").trim();
+    let skipped_lines = meta_data[..meta_data.len() - meta_data.trim_start().len()].matches('\n').count();
+    serde_yaml::from_str(cleaned_meta_data).map_err(|e| {
+        let line = e.location().map(|location| skipped_lines + location.line());
+        (e, line)
+    })
+}
+
+/// A fenced code block of a literate markdown file.
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    /// The first class of the fence, e.g. `python` for ```` ```{.python .cb-code} ````
+    pub language: Option<String>,
+    /// Extension of the file the block is extracted to. Only blocks marked with a Pandoc class
+    /// (`{.python}`) in a supported language are extracted; plain fences stay illustrative.
+    pub extension: Option<String>,
+    /// First and last line of the code between the fences, counted from 1
+    pub start_line: usize,
+    pub end_line: usize,
+    pub content: String,
+}
+
+/// A markdown file split into its front matter and code blocks.
+#[derive(Debug, Clone, Default)]
+pub struct LiterateDocument {
+    /// The file the document was read from, used in errors
+    pub path: Option<PathBuf>,
+    /// The raw YAML between the first pair of `---` lines
+    pub front_matter: Option<String>,
+    /// Line of the file the front matter starts on, counted from 1
+    pub front_matter_line: usize,
+    pub code_blocks: Vec<CodeBlock>,
+}
+
+/// A code block's place in the file it is extracted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLines {
+    pub target_file: String,
+    /// First and last line of the block in the target file, counted from 1
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Reads the classes of a fence info string, supporting both the Pandoc attribute syntax
+/// (`{.python .cb-code}`) and a plain language name (`python`).
+/// The flag tells whether the classes were given with the Pandoc syntax.
+pub fn fence_classes(info: &str) -> (Vec<String>, bool) {
+    let info = info.trim();
+    let attributes = info.strip_prefix('{').and_then(|s| s.strip_suffix('}')).unwrap_or(info);
+    let mut dotted = false;
+    let classes = attributes
+        .split_whitespace()
+        // Skip identifiers (`#id`) and key-value attributes
+        .filter(|attribute| !attribute.starts_with('#') && !attribute.contains('='))
+        .map(|attribute| match attribute.strip_prefix('.') {
+            Some(class) => {
+                dotted = true;
+                class.to_string()
             }
+            None => attribute.to_string(),
+        })
+        .collect();
+    (classes, dotted)
+}
 
-            if !code_blocks.contains_key(&current_lang) {
-                code_blocks.insert(current_lang.clone(), String::new());
+/// Removes a YAML front matter block at the top of the file.
+pub fn strip_front_matter(content: &str) -> &str {
+    let trimmed = content.trim_start();
+    let Some(rest) = trimmed.strip_prefix("---") else {
+        return content;
+    };
+    match rest.find("\n---") {
+        Some(end) => {
+            let after = &rest[end + 4..];
+            after.find('\n').map_or("", |newline| &after[newline + 1..])
+        }
+        None => content,
+    }
+}
+
+impl LiterateDocument {
+    /// Splits a markdown file; blocks in one of `languages` get the extension of their files.
+    pub fn parse(content: &str, languages: &Languages) -> Self {
+        let mut document = LiterateDocument::default();
+        let mut meta_data: Option<String> = None;
+        let mut current_block: Option<CodeBlock> = None;
+        // Like `strip_front_matter`, only a closed `---` block at the top is front matter; later
+        // `---` lines are horizontal rules
+        let front_matter_start = content
+            .lines()
+            .position(|line| !line.trim().is_empty())
+            .filter(|&start| content.lines().nth(start).is_some_and(|line| line.trim() == "---"))
+            .filter(|&start| content.lines().skip(start + 1).any(|line| line.trim() == "---"));
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+
+            if let Some(mut block) = current_block.take() {
+                if line.trim().starts_with("```") {
+                    block.end_line = line_number - 1;
+                    document.code_blocks.push(block);
+                } else {
+                    block.content.push_str(line);
+                    block.content.push('\n');
+                    current_block = Some(block);
+                }
+            } else if let Some(meta) = meta_data.as_mut() {
+                if line.trim() == "---" {
+                    document.front_matter = meta_data.take();
+                } else {
+                    meta.push_str(line);
+                    meta.push('\n');
+                }
+            } else if Some(index) == front_matter_start {
+                meta_data = Some(String::new());
+                document.front_matter_line = line_number + 1;
+            } else if let Some(info) = line.trim().strip_prefix("```") {
+                let (classes, dotted) = fence_classes(info);
+                let extension = if dotted {
+                    classes.iter().find_map(|class| languages.extension(class)).map(str::to_string)
+                } else {
+                    None
+                };
+                current_block = Some(CodeBlock {
+                    language: classes.into_iter().next(),
+                    extension,
+                    start_line: line_number + 1,
+                    end_line: line_number,
+                    content: String::new(),
+                });
             }
-        } else if !current_lang.is_empty() {
-            if let Some(code) = code_blocks.get_mut(&current_lang) {
-                code.push_str(&line);
-                code.push('\n');
+        }
+
+        document
+    }
+
+    pub fn read(file_path: &Path, languages: &Languages) -> Result<Self, LeliError> {
+        let content = std::fs::read_to_string(file_path).at(file_path)?;
+        Ok(LiterateDocument { path: Some(file_path.to_path_buf()), ..Self::parse(&content, languages) })
+    }
+
+    /// Parses the front matter, if the document has one.
+    pub fn meta(&self) -> Result<Option<MarkdownMeta>, LeliError> {
+        let Some(front_matter) = self.front_matter.as_deref() else {
+            return Ok(None);
+        };
+        parse_meta(front_matter).map(Some).map_err(|(source, line)| LeliError::Yaml {
+            path: self.path.clone().unwrap_or_default(),
+            line: line.map(|line| self.front_matter_line + line - 1),
+            source,
+        })
+    }
+
+    /// Concatenates the extracted code blocks into one file per language, named after
+    /// `meta.output_filename`.
+    pub fn tangle(&self, meta: &MarkdownMeta) -> BTreeMap<String, String> {
+        let mut files: BTreeMap<String, String> = BTreeMap::new();
+        for block in &self.code_blocks {
+            if let Some(extension) = &block.extension {
+                let target_file = format!("{}.{}", meta.output_filename, extension);
+                files.entry(target_file).or_default().push_str(&block.content);
             }
         }
+        files
     }
 
-    if !found_meta {
-        return Ok(Err("No metadata found".to_string()));
+    /// Returns, for every code block, where it ends up in the tangled files.
+    pub fn output_lines(&self, meta: &MarkdownMeta) -> Vec<Option<OutputLines>> {
+        let mut line_counts: HashMap<&str, usize> = HashMap::new();
+        self.code_blocks
+            .iter()
+            .map(|block| {
+                let extension = block.extension.as_deref()?;
+                let offset = line_counts.entry(extension).or_default();
+                let start_line = *offset + 1;
+                *offset += block.content.lines().count();
+                Some(OutputLines {
+                    target_file: format!("{}.{}", meta.output_filename, extension),
+                    start_line,
+                    end_line: *offset,
+                })
+            })
+            .collect()
     }
+}
+
+/// Writes the code blocks of literate documents into the files named by their front matter,
+/// which is what `leli extract` does with markdown files.
+#[derive(Debug, Clone)]
+pub struct Tangler {
+    output_folder: PathBuf,
+    languages: Languages,
+    copy_other_files: bool,
+    jobs: usize,
+}
 
-    println!("Extracted YAML metadata:\n{}", meta_data);
+impl Tangler {
+    /// Extracts the built-in languages, using every CPU for folders.
+    pub fn new(output_folder: impl Into<PathBuf>) -> Self {
+        Tangler {
+            output_folder: output_folder.into(),
+            languages: Languages::default(),
+            copy_other_files: false,
+            jobs: default_jobs(),
+        }
+    }
 
-    let cleaned_meta_data = meta_data.trim_end_matches("---").trim();
-    let meta: MarkdownMeta = serde_yaml::from_str(cleaned_meta_data).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("YAML parsing error: {}", e),
-        )
-    })?;
-
-    let mut result: HashMap<String, String> = HashMap::new();
-    for (lang, code) in code_blocks {
-        let extension = match lang.as_str() {
-            "python" => "py",
-            "rust" => "rs",
-            _ => continue,
-        };
+    pub fn with_languages(mut self, languages: Languages) -> Self {
+        self.languages = languages;
+        self
+    }
 
-        let mut output_filename = meta.output_filename.clone();
-        output_filename.push_str(&format!(".{}", extension));
-        result.insert(output_filename, code);
+    /// Also copy markdown files without front matter and all other files into the output
+    /// folder, as `leli extract` does.
+    pub fn with_copies(mut self, copy_other_files: bool) -> Self {
+        self.copy_other_files = copy_other_files;
+        self
     }
 
-    Ok(Ok(result))
+    /// Number of files processed at once by [`Tangler::tangle_folder`].
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn output_folder(&self) -> &Path {
+        &self.output_folder
+    }
+
+    pub fn languages(&self) -> &Languages {
+        &self.languages
+    }
+
+    /// Writes the tangled files of a document into the output folder and returns their paths.
+    pub fn write(&self, document: &LiterateDocument, meta: &MarkdownMeta) -> Result<Vec<PathBuf>, LeliError> {
+        write_tangled(document, meta, &self.output_folder)
+    }
+
+    /// Tangles a file into the output folder and returns what was written. A markdown file
+    /// without front matter names no target files, so it is only copied, like any other file,
+    /// when copies are enabled.
+    pub fn extract_file(&self, path: &Path) -> Result<Vec<Event>, LeliError> {
+        self.extract_into(path, &self.output_folder)
+    }
+
+    /// Like [`Tangler::extract_file`], returning only the written files.
+    pub fn tangle_file(&self, path: &Path) -> Result<Vec<PathBuf>, LeliError> {
+        self.extract_file(path).map(|events| written_files(&events))
+    }
+
+    /// Tangles the files of a folder tree selected by `filter`, up to `jobs` at once. The files
+    /// of subfolders go into the same subfolders of the output folder. Events are reported in
+    /// folder order; every file is tried and the failed ones are reported together.
+    pub fn tangle_folder(&self, folder: &Path, filter: &FileFilter) -> Result<Vec<PathBuf>, LeliError> {
+        let mut sources = Vec::new();
+        collect_sources(folder, filter, &mut sources)?;
+
+        let total = sources.len();
+        let mut written = Vec::new();
+        let mut failures = Vec::new();
+        run_parallel(
+            sources,
+            self.jobs,
+            |path| {
+                let relative_folder = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(folder).ok())
+                    .unwrap_or(Path::new(""));
+                let result = self.extract_into(&path, &self.output_folder.join(relative_folder));
+                (path, result)
+            },
+            |(path, result)| match result {
+                Ok(events) => {
+                    written.extend(written_files(&events));
+                    events.into_iter().for_each(report::emit);
+                }
+                Err(e) => failures.push((path, e)),
+            },
+        );
+        report_failures(failures, total)?;
+        Ok(written)
+    }
+
+    fn extract_into(&self, path: &Path, folder: &Path) -> Result<Vec<Event>, LeliError> {
+        if path.extension().and_then(|s| s.to_str()) == Some("md") {
+            let document = LiterateDocument::read(path, &self.languages)?;
+            if let Some(meta) = document.meta()? {
+                return Ok(write_tangled(&document, &meta, folder)?
+                    .into_iter()
+                    .map(|target| Event::Extracted { source: path.to_path_buf(), target })
+                    .collect());
+            }
+        }
+        if !self.copy_other_files {
+            return Ok(Vec::new());
+        }
+        copy_to_folder(path, folder).map(|event| vec![event])
+    }
+}
+
+fn write_tangled(document: &LiterateDocument, meta: &MarkdownMeta, folder: &Path) -> Result<Vec<PathBuf>, LeliError> {
+    let mut written = Vec::new();
+    for (filename, code) in document.tangle(meta) {
+        let file_output_path = folder.join(filename);
+        if let Some(parent) = file_output_path.parent() {
+            std::fs::create_dir_all(parent).at(parent)?;
+        }
+        let mut output_file = File::create(&file_output_path).at(&file_output_path)?;
+        output_file.write_all(code.as_bytes()).at(&file_output_path)?;
+        written.push(file_output_path);
+    }
+    Ok(written)
 }
 
-pub fn extract_code_from_folder(folder_path: &str, app_folder: &str) -> io::Result<()> {
-    for entry in std::fs::read_dir(folder_path)? {
-        let entry = entry?;
-        let path = entry.path();
+fn copy_to_folder(path: &Path, folder: &Path) -> Result<Event, LeliError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| LeliError::Usage(format!("{} is not a file", path.display())))?;
+    std::fs::create_dir_all(folder).at(folder)?;
+    let output_path = folder.join(file_name);
+    std::fs::copy(path, &output_path).at(path)?;
+    Ok(Event::Copied { source: path.to_path_buf(), target: output_path })
+}
+
+fn written_files(events: &[Event]) -> Vec<PathBuf> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Extracted { target, .. } | Event::Copied { target, .. } => Some(target.clone()),
+            _ => None,
+        })
+        .collect()
+}
 
+/// Lists the files of a folder tree selected by `filter`, in folder order. The skipped files
+/// and folders are reported.
+pub(crate) fn collect_sources(folder: &Path, filter: &FileFilter, files: &mut Vec<PathBuf>) -> Result<(), LeliError> {
+    for path in sorted_dir_entries(folder).at(folder)? {
         if path.is_dir() {
-            let sub_app_folder = PathBuf::from(app_folder).join(path.file_name().unwrap());
-            std::fs::create_dir_all(&sub_app_folder)?;
-            extract_code_from_folder(path.to_str().unwrap(), sub_app_folder.to_str().unwrap())?;
+            if filter.skips_dir(&path) {
+                report::emit(Event::Skipped { path, reason: SkipReason::Ignored });
+            } else {
+                collect_sources(&path, filter, files)?;
+            }
         } else if path.is_file() {
-            if path.extension().and_then(|s| s.to_str()) == Some("md") {
-                match extract_code_from_markdown(path.to_str().unwrap()) {
-                    Ok(Ok(extracted_code)) => {
-                        for (filename, code) in extracted_code {
-                            let file_output_path = PathBuf::from(app_folder).join(filename);
-                            if let Some(parent) = file_output_path.parent() {
-                                std::fs::create_dir_all(parent)?;
-                            }
-                            let mut output_file = File::create(&file_output_path)?;
-                            output_file.write_all(code.as_bytes())?;
-                            println!("Code extracted to {}", file_output_path.display());
-                        }
-                    }
-                    Ok(Err(_)) => {
-                        // Copy simple markdown file to .app folder
-                        let output_path = PathBuf::from(app_folder).join(path.file_name().unwrap());
-                        std::fs::copy(&path, &output_path)?;
-                        println!("Copied file to {}", output_path.display());
-                    }
-                    Err(e) => {
-                        eprintln!("Error processing file {}: {}", path.display(), e);
-                    }
-                }
+            if filter.allows(&path) {
+                files.push(path);
             } else {
-                // Copy non-markdown file to app folder
-                let output_path = PathBuf::from(app_folder).join(path.file_name().unwrap());
-                std::fs::copy(&path, &output_path)?;
-                println!("Copied file to {}", output_path.display());
+                report::emit(Event::Skipped { path, reason: SkipReason::Ignored });
             }
         }
     }
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> LiterateDocument {
+        LiterateDocument::parse(content, &Languages::default())
+    }
+
+    #[test]
+    fn horizontal_rules_are_not_front_matter() {
+        let document = parse("# T\n\n---\n\n```{.python}\nx = 1\n```\n\n---\n");
+        assert!(document.front_matter.is_none());
+        assert_eq!(document.code_blocks.len(), 1);
+        assert_eq!(document.code_blocks[0].content, "x = 1\n");
+    }
+
+    #[test]
+    fn front_matter_starts_at_the_first_non_blank_line() {
+        let document = parse("\n---\noutput_filename: x\n---\ntext\n\n---\n");
+        assert_eq!(document.front_matter.as_deref(), Some("output_filename: x\n"));
+        assert_eq!(document.front_matter_line, 3);
+    }
+
+    #[test]
+    fn unclosed_front_matter_is_ignored() {
+        let document = parse("---\ntitle: x\n```{.python}\nx = 1\n```\n");
+        assert!(document.front_matter.is_none());
+        assert_eq!(document.code_blocks.len(), 1);
+    }
+
+    #[test]
+    fn languages_are_chosen_per_document() {
+        let content = "```{.go}\nfunc main() {}\n```\n";
+        assert_eq!(parse(content).code_blocks[0].extension, None);
+
+        let languages = Languages::new(BTreeMap::from([("go".to_string(), "go".to_string())]));
+        let document = LiterateDocument::parse(content, &languages);
+        assert_eq!(document.code_blocks[0].extension.as_deref(), Some("go"));
+        assert_eq!(languages.extension("python"), Some("py"));
+    }
+
+    #[test]
+    fn tangle_folder_mirrors_subfolders() {
+        let root = std::env::temp_dir().join(format!("leli-tangle-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let docs = root.join("docs");
+        std::fs::create_dir_all(docs.join("sub")).unwrap();
+        std::fs::write(docs.join("sub/a.md"), "---\noutput_filename: a\n---\n```{.python}\nx = 1\n```\n").unwrap();
+        std::fs::write(docs.join("notes.txt"), "notes").unwrap();
+        let filter = FileFilter::new(&docs, &[], &[], false).unwrap();
+
+        let written = Tangler::new(root.join("code")).with_jobs(2).tangle_folder(&docs, &filter).unwrap();
+        assert_eq!(written, vec![root.join("code/sub/a.py")]);
+        assert_eq!(std::fs::read_to_string(root.join("code/sub/a.py")).unwrap(), "x = 1\n");
+
+        let written = Tangler::new(root.join("app")).with_copies(true).tangle_folder(&docs, &filter).unwrap();
+        assert_eq!(written, vec![root.join("app/notes.txt"), root.join("app/sub/a.py")]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
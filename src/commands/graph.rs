@@ -0,0 +1,424 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use kuchikiki::iter::NodeIterator;
+use kuchikiki::NodeRef;
+
+use crate::commands::html::select_all;
+use crate::commands::models::DocLink;
+use crate::schema::{code_blocks, doc_links, html_metadata};
+
+/// Kind of the edge from a page to a page it links to.
+pub const LINK_KIND: &str = "link";
+/// Kind of the edge from a page to the document that generates a file the page refers to.
+pub const CHUNK_KIND: &str = "chunk";
+
+/// Format `leli graph` exports the document graph in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, e.g. for `dot -Tsvg`
+    #[default]
+    Dot,
+    /// A Mermaid flowchart that can be pasted into a literate doc
+    Mermaid,
+    /// Nodes and edges for other tools
+    Json,
+}
+
+/// The references of a page, as found in its HTML before they are resolved against the database.
+#[derive(Debug, Clone, Default)]
+pub struct PageReferences {
+    /// Pages the page links to, resolved against the page's folder
+    pub links: Vec<String>,
+    /// Other local link targets and inline code, which may name a file generated by another document
+    pub mentions: Vec<String>,
+}
+
+impl PageReferences {
+    pub fn read(document: &NodeRef, html_path: &str) -> Self {
+        let folder = Path::new(html_path).parent().unwrap_or(Path::new(""));
+        let mut references = PageReferences::default();
+
+        for link in select_all(document, "a[href]") {
+            let attributes = link.attributes.borrow();
+            let href = attributes.get("href").unwrap_or_default();
+            if href.contains("://") || href.starts_with("mailto:") {
+                continue;
+            }
+            let path = href.split(['#', '?']).next().unwrap_or_default();
+            if path.is_empty() {
+                continue;
+            }
+            if path.ends_with(".html") {
+                let target = normalize_path(&folder.join(path));
+                references.links.push(target.to_string_lossy().into_owned());
+            } else {
+                references.mentions.push(path.to_string());
+            }
+        }
+
+        for code in select_all(document, "code") {
+            let in_block = code.as_node().ancestors().elements().any(|element| &*element.name.local == "pre");
+            let text = code.text_contents();
+            let text = text.trim();
+            if !in_block && text.contains('.') && !text.contains(char::is_whitespace) {
+                references.mentions.push(text.to_string());
+            }
+        }
+
+        references
+    }
+}
+
+/// Resolves `.` and `..` without touching the file system, so paths match the saved ones.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Loads every generated file together with the page of the document that generates it.
+pub fn generated_file_owners(conn: &mut SqliteConnection) -> QueryResult<Vec<(String, String)>> {
+    let owners: Vec<(Option<String>, String)> = code_blocks::table
+        .inner_join(html_metadata::table.on(html_metadata::id.eq(code_blocks::document_id.nullable())))
+        .filter(code_blocks::target_file.is_not_null())
+        .select((code_blocks::target_file, html_metadata::file_path))
+        .distinct()
+        .load(conn)?;
+    Ok(owners
+        .into_iter()
+        .filter_map(|(target_file, owner)| Some((target_file?, owner)))
+        .collect())
+}
+
+/// Tells whether `path` names a generated file stored as `target_file`, which is relative to
+/// the app folder. Either may be the shorter one, so `api.py` names every such file, while
+/// `.app/a/public/api.py` only names that of `a/public`. The AImM protocol combines `private`
+/// and `public` into `src`, so `a/src/api.py` names it too.
+pub fn names_generated_file(path: &str, target_file: &str) -> bool {
+    let path = Path::new(path);
+    if path.as_os_str().is_empty() {
+        return false;
+    }
+    let target = Path::new(target_file);
+    let combined: PathBuf = target
+        .iter()
+        .map(|name| if name == "private" || name == "public" { "src".as_ref() } else { name })
+        .collect();
+    [target, combined.as_path()]
+        .iter()
+        .any(|target| path.ends_with(target) || target.ends_with(path))
+}
+
+/// Replaces the stored links of a page. Mentions become chunk edges when they name a file
+/// generated by another document; other mentions are dropped.
+pub fn save_doc_links(
+    conn: &mut SqliteConnection,
+    source_id: i32,
+    page_path: &str,
+    references: &PageReferences,
+    owners: &[(String, String)],
+) -> QueryResult<()> {
+    diesel::delete(doc_links::table.filter(doc_links::source_id.eq(source_id))).execute(conn)?;
+
+    let mut edges: BTreeSet<(String, &str, Option<String>)> = BTreeSet::new();
+    for link in &references.links {
+        if link != page_path {
+            edges.insert((link.clone(), LINK_KIND, None));
+        }
+    }
+    for mention in &references.mentions {
+        for (target_file, owner) in owners {
+            if owner != page_path && names_generated_file(mention, target_file) {
+                edges.insert((owner.clone(), CHUNK_KIND, Some(target_file.clone())));
+            }
+        }
+    }
+
+    let links: Vec<DocLink> = edges
+        .into_iter()
+        .map(|(target_path, kind, label)| DocLink {
+            id: None,
+            source_id,
+            target_path,
+            kind: kind.to_string(),
+            label,
+        })
+        .collect();
+    diesel::insert_into(doc_links::table).values(&links).execute(conn)?;
+    Ok(())
+}
+
+/// A page in the document graph.
+pub struct GraphNode {
+    pub path: String,
+    pub title: Option<String>,
+    pub service: Option<String>,
+    /// No other page links to or refers to this page
+    pub orphan: bool,
+    /// The page is linked to but was never saved, i.e. the link is broken
+    pub missing: bool,
+}
+
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub kind: String,
+    pub label: Option<String>,
+}
+
+/// The saved documents and the references between them.
+pub struct DocGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Builds the document graph from the database.
+pub fn load_graph(conn: &mut SqliteConnection) -> QueryResult<DocGraph> {
+    let documents: Vec<(String, Option<String>, Option<String>)> = html_metadata::table
+        .select((html_metadata::file_path, html_metadata::title, html_metadata::service_name))
+        .order(html_metadata::file_path)
+        .load(conn)?;
+    let edges: Vec<GraphEdge> = doc_links::table
+        .inner_join(html_metadata::table.on(html_metadata::id.eq(doc_links::source_id.nullable())))
+        .select((html_metadata::file_path, doc_links::target_path, doc_links::kind, doc_links::label))
+        .order((html_metadata::file_path, doc_links::target_path))
+        .load::<(String, String, String, Option<String>)>(conn)?
+        .into_iter()
+        .map(|(source, target, kind, label)| GraphEdge { source, target, kind, label })
+        .collect();
+
+    let referenced: BTreeSet<&str> = edges.iter().map(|edge| edge.target.as_str()).collect();
+    let mut nodes: Vec<GraphNode> = documents
+        .into_iter()
+        .map(|(path, title, service)| GraphNode {
+            orphan: !referenced.contains(path.as_str()),
+            path,
+            title,
+            service,
+            missing: false,
+        })
+        .collect();
+
+    let saved: BTreeSet<String> = nodes.iter().map(|node| node.path.clone()).collect();
+    for target in referenced {
+        if !saved.contains(target) {
+            nodes.push(GraphNode {
+                path: target.to_string(),
+                title: None,
+                service: None,
+                orphan: false,
+                missing: true,
+            });
+        }
+    }
+
+    Ok(DocGraph { nodes, edges })
+}
+
+impl DocGraph {
+    pub fn orphans(&self) -> impl Iterator<Item = &GraphNode> {
+        self.nodes.iter().filter(|node| node.orphan)
+    }
+
+    pub fn format(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Groups the nodes by service; nodes without a service come first under `None`.
+    fn nodes_by_service(&self) -> BTreeMap<Option<&str>, Vec<(usize, &GraphNode)>> {
+        let mut services: BTreeMap<Option<&str>, Vec<(usize, &GraphNode)>> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            services.entry(node.service.as_deref()).or_default().push((index, node));
+        }
+        services
+    }
+
+    fn node_label(node: &GraphNode) -> &str {
+        node.title.as_deref().unwrap_or(&node.path)
+    }
+
+    fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph docs {\n    rankdir=LR;\n    node [shape=box];\n");
+
+        for (service, nodes) in self.nodes_by_service() {
+            let indent = if service.is_some() { "        " } else { "    " };
+            if let Some(service) = service {
+                dot.push_str(&format!("    subgraph {} {{\n        label={};\n", quote(&format!("cluster_{}", service)), quote(service)));
+            }
+            for (_, node) in nodes {
+                let style = if node.missing {
+                    ", style=dashed, color=red"
+                } else if node.orphan {
+                    ", style=filled, fillcolor=orange"
+                } else {
+                    ""
+                };
+                dot.push_str(&format!(
+                    "{}{} [label={}, tooltip={}{}];\n",
+                    indent,
+                    quote(&node.path),
+                    quote(Self::node_label(node)),
+                    quote(&node.path),
+                    style
+                ));
+            }
+            if service.is_some() {
+                dot.push_str("    }\n");
+            }
+        }
+
+        for edge in &self.edges {
+            let attributes = match (&edge.kind[..], &edge.label) {
+                (CHUNK_KIND, Some(label)) => format!(" [style=dashed, label={}]", quote(label)),
+                (CHUNK_KIND, None) => " [style=dashed]".to_string(),
+                _ => String::new(),
+            };
+            dot.push_str(&format!("    {} -> {}{};\n", quote(&edge.source), quote(&edge.target), attributes));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        // A line break would end the statement, so labels are kept on one line
+        let quote = |text: &str| format!("\"{}\"", text.replace('"', "#quot;").replace(['\r', '\n'], " "));
+        let ids: BTreeMap<&str, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.path.as_str(), format!("doc{}", index)))
+            .collect();
+        let mut mermaid = String::from("flowchart LR\n");
+
+        for (group, (service, nodes)) in self.nodes_by_service().into_iter().enumerate() {
+            let indent = if service.is_some() { "        " } else { "    " };
+            if let Some(service) = service {
+                mermaid.push_str(&format!("    subgraph service{} [{}]\n", group, quote(service)));
+            }
+            for (index, node) in nodes {
+                mermaid.push_str(&format!("{}doc{}[{}]\n", indent, index, quote(Self::node_label(node))));
+            }
+            if service.is_some() {
+                mermaid.push_str("    end\n");
+            }
+        }
+
+        for edge in &self.edges {
+            let arrow = match (&edge.kind[..], &edge.label) {
+                (CHUNK_KIND, Some(label)) => format!("-. {} .->", quote(label)),
+                (CHUNK_KIND, None) => "-.->".to_string(),
+                _ => "-->".to_string(),
+            };
+            mermaid.push_str(&format!("    {} {} {}\n", ids[edge.source.as_str()], arrow, ids[edge.target.as_str()]));
+        }
+
+        mermaid.push_str("    classDef orphan fill:#fdba74\n    classDef missing stroke:#dc2626,stroke-dasharray:4\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.orphan {
+                mermaid.push_str(&format!("    class doc{} orphan\n", index));
+            } else if node.missing {
+                mermaid.push_str(&format!("    class doc{} missing\n", index));
+            }
+        }
+        mermaid
+    }
+
+    /// The graph as a JSON object with `nodes` and `edges`.
+    pub fn json(&self) -> serde_json::Value {
+        let nodes: Vec<serde_json::Value> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                serde_json::json!({
+                    "path": node.path,
+                    "title": node.title,
+                    "service": node.service,
+                    "orphan": node.orphan,
+                    "missing": node.missing,
+                })
+            })
+            .collect();
+        let edges: Vec<serde_json::Value> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                serde_json::json!({
+                    "source": edge.source,
+                    "target": edge.target,
+                    "kind": edge.kind,
+                    "label": edge.label,
+                })
+            })
+            .collect();
+        serde_json::json!({ "nodes": nodes, "edges": edges })
+    }
+
+    fn to_json(&self) -> String {
+        format!("{}\n", serde_json::to_string_pretty(&self.json()).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(path: &str, title: &str) -> GraphNode {
+        GraphNode {
+            path: path.to_string(),
+            title: Some(title.to_string()),
+            service: None,
+            orphan: false,
+            missing: false,
+        }
+    }
+
+    fn graph() -> DocGraph {
+        DocGraph {
+            nodes: vec![
+                node("doc/a_combined.html", "The \"quoted\" \\ guide"),
+                node("doc/b_combined.html", "Two\nlines"),
+            ],
+            edges: vec![GraphEdge {
+                source: "doc/a_combined.html".to_string(),
+                target: "doc/b_combined.html".to_string(),
+                kind: CHUNK_KIND.to_string(),
+                label: Some("say \"hi\".py".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn dot_escapes_quotes_and_backslashes() {
+        let dot = graph().format(GraphFormat::Dot);
+
+        assert!(dot.contains(r#"[label="The \"quoted\" \\ guide", tooltip="doc/a_combined.html"];"#), "{}", dot);
+        assert!(dot.contains(r#""doc/a_combined.html" -> "doc/b_combined.html" [style=dashed, label="say \"hi\".py"];"#), "{}", dot);
+    }
+
+    #[test]
+    fn mermaid_escapes_quotes_and_keeps_labels_on_one_line() {
+        let mermaid = graph().format(GraphFormat::Mermaid);
+
+        assert!(mermaid.contains(r#"doc0["The #quot;quoted#quot; \ guide"]"#), "{}", mermaid);
+        assert!(mermaid.contains(r#"doc1["Two lines"]"#), "{}", mermaid);
+        assert!(mermaid.contains(r#"doc0 -. "say #quot;hi#quot;.py" .-> doc1"#), "{}", mermaid);
+    }
+}
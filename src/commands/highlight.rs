@@ -0,0 +1,71 @@
+use std::io;
+use std::sync::OnceLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{append_highlighted_html_for_styled_line, IncludeBackground};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::commands::html::escape_html;
+
+/// Built-in theme used for `--theme light`.
+const LIGHT_THEME: &str = "InspiredGitHub";
+/// Built-in theme used for `--theme dark`; the dark mode of `src/css/style.css` uses its colours.
+const DARK_THEME: &str = "base16-ocean.dark";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme_set() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Looks up a highlighting theme by name; `light` and `dark` are shortcuts for the themes
+/// matching the default stylesheet.
+pub fn resolve_theme(name: &str) -> io::Result<&'static Theme> {
+    let theme_name = match name {
+        "light" => LIGHT_THEME,
+        "dark" => DARK_THEME,
+        other => other,
+    };
+
+    theme_set().themes.get(theme_name).ok_or_else(|| {
+        let available: Vec<&str> = theme_set().themes.keys().map(String::as_str).collect();
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "unknown theme '{}', use light, dark or one of: {}",
+                name,
+                available.join(", ")
+            ),
+        )
+    })
+}
+
+/// Highlights a code block with inline styles, so the page needs no extra stylesheet.
+/// Returns `None` if no syntax definition knows the language.
+pub fn highlight_code(code: &str, lang: &str, classes: &str, theme: &Theme) -> Option<String> {
+    let syntax = syntax_set().find_syntax_by_token(lang)?;
+    let background = theme.settings.background.unwrap_or(syntect::highlighting::Color::WHITE);
+
+    let mut html = format!(
+        "<pre class=\"{}\" style=\"background-color:#{:02x}{:02x}{:02x};\"><code>",
+        escape_html(classes),
+        background.r,
+        background.g,
+        background.b
+    );
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    for line in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, syntax_set()).ok()?;
+        append_highlighted_html_for_styled_line(&regions, IncludeBackground::IfDifferent(background), &mut html)
+            .ok()?;
+    }
+    html.push_str("</code></pre>\n");
+
+    Some(html)
+}
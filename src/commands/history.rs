@@ -0,0 +1,122 @@
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::commands::models::{DocRevision, SaveRun};
+use crate::commands::query::QueryResultTable;
+use crate::commands::save::{sha256_hex, DocumentRecord};
+use crate::schema::{doc_revisions, html_metadata, save_runs};
+
+/// Hashes the code of all code blocks of a document, so a revision tells whether the files
+/// generated from the document changed.
+fn code_hash(document: &DocumentRecord) -> String {
+    let block_hashes: Vec<&str> = document
+        .code_blocks
+        .iter()
+        .map(|block| block.content_hash.as_str())
+        .collect();
+    sha256_hex(block_hashes.join("\n").as_bytes())
+}
+
+/// Starts a save run and returns its id. The run counts no documents until
+/// [`finish_save_run`].
+pub fn start_save_run(conn: &mut SqliteConnection) -> QueryResult<i32> {
+    diesel::insert_into(save_runs::table)
+        .values(&SaveRun {
+            id: None,
+            started_at: Utc::now().naive_utc(),
+            leli_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            document_count: 0,
+        })
+        .execute(conn)?;
+    Ok(save_runs::table
+        .select(save_runs::id)
+        .order(save_runs::id.desc())
+        .first::<Option<i32>>(conn)?
+        .unwrap_or_default())
+}
+
+/// Records how many documents a save run saved.
+pub fn finish_save_run(conn: &mut SqliteConnection, run_id: i32, document_count: usize) -> QueryResult<()> {
+    diesel::update(save_runs::table.filter(save_runs::id.eq(run_id)))
+        .set(save_runs::document_count.eq(document_count as i32))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records a revision of the document in the save run if its page or code differ from its last
+/// revision. Returns whether a revision was recorded.
+pub fn record_revision(conn: &mut SqliteConnection, run_id: i32, document: &DocumentRecord) -> QueryResult<bool> {
+    let metadata = &document.metadata;
+    let code_hash = code_hash(document);
+    let last_revision = doc_revisions::table
+        .filter(doc_revisions::document_path.eq(&metadata.file_path))
+        .order(doc_revisions::id.desc())
+        .select((doc_revisions::content_hash, doc_revisions::code_hash))
+        .first::<(Option<String>, String)>(conn)
+        .optional()?;
+    if last_revision == Some((metadata.content_hash.clone(), code_hash.clone())) {
+        return Ok(false);
+    }
+
+    diesel::insert_into(doc_revisions::table)
+        .values(&DocRevision {
+            id: None,
+            run_id,
+            document_path: metadata.file_path.clone(),
+            content_hash: metadata.content_hash.clone(),
+            size: document.size,
+            generated_at: metadata.generated_at,
+            code_hash,
+        })
+        .execute(conn)?;
+    Ok(true)
+}
+
+fn short_hash(hash: &str) -> String {
+    hash.chars().take(12).collect()
+}
+
+/// Lists the revisions of a document, oldest first. `document` is the path of the page or of
+/// the markdown file it was generated from.
+pub fn document_history(conn: &mut SqliteConnection, document: &str) -> QueryResult<QueryResultTable> {
+    let mut paths: Vec<String> = html_metadata::table
+        .filter(html_metadata::source_path.eq(document))
+        .select(html_metadata::file_path)
+        .load(conn)?;
+    paths.push(document.to_string());
+
+    let revisions: Vec<(DocRevision, SaveRun)> = doc_revisions::table
+        .inner_join(save_runs::table.on(save_runs::id.eq(doc_revisions::run_id.nullable())))
+        .filter(doc_revisions::document_path.eq_any(&paths))
+        .order(doc_revisions::id)
+        .load(conn)?;
+
+    let mut table = QueryResultTable::new(vec![
+        "run", "saved_at", "document", "page", "code", "size", "content_hash", "code_hash",
+    ]);
+    let mut previous: Vec<(String, Option<String>, String)> = Vec::new();
+    for (revision, run) in revisions {
+        let last = previous.iter().rev().find(|(path, _, _)| *path == revision.document_path);
+        let (page, code) = match last {
+            None => ("added", "added"),
+            Some((_, content_hash, code_hash)) => (
+                if *content_hash != revision.content_hash { "changed" } else { "-" },
+                if *code_hash != revision.code_hash { "changed" } else { "-" },
+            ),
+        };
+
+        table.rows.push(vec![
+            run.id.unwrap_or_default().to_string(),
+            run.started_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            revision.document_path.clone(),
+            page.to_string(),
+            code.to_string(),
+            revision.size.to_string(),
+            short_hash(revision.content_hash.as_deref().unwrap_or_default()),
+            short_hash(&revision.code_hash),
+        ]);
+        previous.push((revision.document_path, revision.content_hash, revision.code_hash));
+    }
+    Ok(table)
+}
@@ -0,0 +1,286 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use kuchikiki::traits::*;
+use kuchikiki::{ElementData, NodeDataRef, NodeRef};
+
+use crate::commands::extract::{CodeBlock, MarkdownMeta};
+
+/// Name of the `<meta>` tag that records the markdown file a page was generated from.
+pub const SOURCE_META: &str = "leli-source";
+/// Name of the `<meta>` tag that records the leli version a page was generated with.
+pub const VERSION_META: &str = "leli-version";
+/// Name of the `<meta>` tag that records the folder of the page below the doc output. It is
+/// the folder of the markdown file below the input folder, and so the folder its code is
+/// extracted to below the app folder.
+pub const FOLDER_META: &str = "leli-folder";
+
+/// An HTML page going through the post-processing pipeline.
+pub struct Page<'a> {
+    /// The generated HTML file, rewritten in place by the pipeline
+    pub html_path: &'a Path,
+    /// The markdown file the page was generated from
+    pub source_path: &'a Path,
+    /// The root of the doc output the page belongs to
+    pub doc_folder: &'a Path,
+    /// The front matter of the markdown file the page was generated from
+    pub meta: Option<MarkdownMeta>,
+    /// The fenced code blocks of the markdown file, in document order
+    pub code_blocks: Vec<CodeBlock>,
+    /// Non-fatal problems reported by the transforms
+    pub warnings: Vec<String>,
+}
+
+/// A DOM-level transform stage of the pipeline.
+pub trait Transform {
+    /// Short name used to tell which stage an error came from.
+    fn name(&self) -> &'static str;
+
+    /// Modifies the parsed document in place.
+    fn apply(&self, document: &NodeRef, page: &mut Page) -> io::Result<()>;
+}
+
+/// Parses an HTML page once, runs all transforms on the DOM and writes the page back.
+#[derive(Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a transform; transforms run in the order they were added.
+    pub fn with(mut self, transform: impl Transform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn run(&self, page: &mut Page) -> io::Result<()> {
+        let html_content = fs::read_to_string(page.html_path)?;
+        let document = kuchikiki::parse_html().one(html_content);
+
+        for transform in &self.transforms {
+            transform.apply(&document, page).map_err(|e| {
+                io::Error::new(e.kind(), format!("{} failed: {}", transform.name(), e))
+            })?;
+        }
+
+        document.serialize_to_file(page.html_path)
+    }
+}
+
+/// Parses an HTML snippet into nodes that can be inserted into a document.
+pub fn parse_fragment(html: &str) -> Vec<NodeRef> {
+    let document = kuchikiki::parse_html().one(format!("<!DOCTYPE html><html><body>{}</body></html>", html));
+    match document.select_first("body") {
+        Ok(body) => body.as_node().children().collect(),
+        Err(()) => Vec::new(),
+    }
+}
+
+/// The elements matching a CSS selector; an invalid selector matches nothing.
+pub fn select_all(document: &NodeRef, selector: &str) -> impl Iterator<Item = NodeDataRef<ElementData>> {
+    document.select(selector).into_iter().flatten()
+}
+
+/// Escapes text for use in HTML content and attribute values.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Finds the element a transform inserts into, failing if the page does not have it.
+pub fn insertion_point(document: &NodeRef, selector: &str, page: &Page) -> io::Result<NodeRef> {
+    document
+        .select_first(selector)
+        .map(|element| element.as_node().clone())
+        .map_err(|()| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no <{}> element in {}", selector, page.html_path.display()),
+            )
+        })
+}
+
+/// Builds the relative URL from an HTML page to a file given relative to the doc root.
+pub fn relative_to_doc_root(page: &Page, target: &str) -> String {
+    relative_url(page.html_path, page.doc_folder, target)
+}
+
+/// Builds the relative URL from the page at `html_path` to `target` below `doc_folder`.
+pub fn relative_url(html_path: &Path, doc_folder: &Path, target: &str) -> String {
+    let depth = html_path
+        .parent()
+        .and_then(|parent| parent.strip_prefix(doc_folder).ok())
+        .map(|relative| relative.components().count())
+        .unwrap_or(0);
+    format!("{}{}", "../".repeat(depth), target)
+}
+
+/// Points links to other markdown documents at their translated HTML pages.
+pub struct RewriteLinks;
+
+impl Transform for RewriteLinks {
+    fn name(&self) -> &'static str {
+        "link rewriting"
+    }
+
+    fn apply(&self, document: &NodeRef, _page: &mut Page) -> io::Result<()> {
+        for link in select_all(document, "a[href]") {
+            let mut attributes = link.attributes.borrow_mut();
+            if let Some(href) = attributes.get_mut("href") {
+                if let Some(rewritten) = markdown_link_to_html(href) {
+                    *href = rewritten;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps `other.md#section` to `other_combined.html#section`; other links are left alone.
+fn markdown_link_to_html(href: &str) -> Option<String> {
+    if href.contains("://") || href.starts_with("mailto:") {
+        return None;
+    }
+
+    let (path, fragment) = match href.find('#') {
+        Some(index) => href.split_at(index),
+        None => (href, ""),
+    };
+    let stem = path.strip_suffix(".md")?;
+    Some(format!("{}_combined.html{}", stem, fragment))
+}
+
+/// Marks every extracted code block with the file it is extracted to. The `<pre>` elements
+/// of the page are matched with the code blocks of the markdown file in document order, so
+/// this runs before diagrams are replaced.
+pub struct AnnotateCode;
+
+impl Transform for AnnotateCode {
+    fn name(&self) -> &'static str {
+        "code annotation"
+    }
+
+    fn apply(&self, document: &NodeRef, page: &mut Page) -> io::Result<()> {
+        let Some(meta) = &page.meta else {
+            return Ok(());
+        };
+
+        for (block, code_block) in select_all(document, "pre").zip(&page.code_blocks) {
+            let Some(extension) = &code_block.extension else {
+                continue;
+            };
+            let mut classes: Vec<String> = Vec::new();
+            for element in block.as_node().inclusive_descendants().elements() {
+                if let Some(class) = element.attributes.borrow().get("class") {
+                    classes.extend(class.split_whitespace().map(str::to_string));
+                }
+            }
+            // A `<pre>` that did not come from a fence, e.g. an indented block, shifts the
+            // matching; leave blocks of another language unmarked rather than mislabel them
+            if !classes.iter().any(|class| Some(class) == code_block.language.as_ref()) {
+                continue;
+            }
+
+            let target = format!("{}.{}", meta.output_filename, extension);
+            let mut attributes = block.attributes.borrow_mut();
+            attributes.insert("title", format!("Extracted to {}", target));
+            attributes.insert("data-target", target);
+        }
+        Ok(())
+    }
+}
+
+/// Records in the page head which markdown file and leli version produced the page and the
+/// folder it is in, so `save` can store them alongside the page.
+pub struct RecordProvenance;
+
+impl Transform for RecordProvenance {
+    fn name(&self) -> &'static str {
+        "provenance"
+    }
+
+    fn apply(&self, document: &NodeRef, page: &mut Page) -> io::Result<()> {
+        let head = insertion_point(document, "head", page)?;
+        let folder: Vec<String> = page
+            .html_path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(page.doc_folder).ok())
+            .map(|folder| folder.iter().map(|name| name.to_string_lossy().into_owned()).collect())
+            .unwrap_or_default();
+        let tags = format!(
+            "<meta name=\"{}\" content=\"{}\"><meta name=\"{}\" content=\"{}\"><meta name=\"{}\" content=\"{}\">",
+            SOURCE_META,
+            escape_html(&page.source_path.to_string_lossy()),
+            VERSION_META,
+            env!("CARGO_PKG_VERSION"),
+            FOLDER_META,
+            escape_html(&folder.join("/"))
+        );
+        for node in parse_fragment(&tags) {
+            head.append(node);
+        }
+        Ok(())
+    }
+}
+
+/// Reads the `content` of a named `<meta>` tag.
+pub fn meta_content(document: &NodeRef, name: &str) -> Option<String> {
+    let element = document.select_first(&format!("meta[name=\"{}\"]", name)).ok()?;
+    let content = element.attributes.borrow().get("content").map(str::to_string);
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::extract::{Languages, LiterateDocument};
+
+    fn annotate(markdown: &str, html: &str) -> NodeRef {
+        let document = kuchikiki::parse_html().one(html);
+        let literate = LiterateDocument::parse(markdown, &Languages::default());
+        let mut page = Page {
+            html_path: Path::new("doc/billing_combined.html"),
+            source_path: Path::new("billing.md"),
+            doc_folder: Path::new("doc"),
+            meta: literate.meta().unwrap(),
+            code_blocks: literate.code_blocks,
+            warnings: Vec::new(),
+        };
+        AnnotateCode.apply(&document, &mut page).unwrap();
+        document
+    }
+
+    fn targets(document: &NodeRef) -> Vec<Option<String>> {
+        select_all(document, "pre")
+            .map(|block| block.attributes.borrow().get("data-target").map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn only_extracted_blocks_get_a_target() {
+        let markdown = "---\noutput_filename: billing\n---\n```python\nillustration()\n```\n```{.python}\ncode()\n```\n";
+        let html = "<pre class=\"python\"><code>illustration()</code></pre>\
+                    <pre class=\"sourceCode python\"><code>code()</code></pre>";
+
+        let document = annotate(markdown, html);
+
+        assert_eq!(targets(&document), vec![None, Some("billing.py".to_string())]);
+    }
+
+    #[test]
+    fn blocks_of_another_language_are_not_mislabelled() {
+        let markdown = "---\noutput_filename: billing\n---\n```{.python}\ncode()\n```\n";
+        let html = "<pre><code>indented</code></pre><pre class=\"python\"><code>code()</code></pre>";
+
+        let document = annotate(markdown, html);
+
+        assert_eq!(targets(&document), vec![None, None]);
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::assets::{PAGE_TEMPLATE, STYLE_CSS};
+use crate::config::CONFIG_FILE;
+use crate::error::{IoContext, LeliError};
+use crate::report::{self, Event, SkipReason};
+
+/// Folder of the literate markdown files in a new project.
+const DOCS_FOLDER: &str = "docs";
+/// Where `--assets` puts the stylesheet and page template for customization.
+const STYLESHEET_PATH: &str = "assets/style.css";
+const TEMPLATE_PATH: &str = "assets/template.html";
+/// Entries every project's `.gitignore` needs: the extracted code, the generated pages and the
+/// database of the starter config.
+const GITIGNORE_ENTRIES: &[&str] = &[".app/", "doc/", "leli.db"];
+
+fn starter_config(service: Option<&str>, assets: bool) -> String {
+    let mut config = format!(
+        "# Settings of leli, run `leli config show` to see all of them\n\
+         input = \"{}\"\n",
+        DOCS_FOLDER
+    );
+    if service.is_some() {
+        config.push_str("protocol = \"AImM\"\n");
+    }
+    config.push_str(
+        "exclude = [\"drafts/**\"]\n\
+         \n\
+         [extract]\n\
+         output = \".app\"\n\
+         \n\
+         [translate]\n\
+         output = \"doc\"\n\
+         engine = \"native\"\n",
+    );
+    if assets {
+        config.push_str(&format!(
+            "stylesheet = \"{}\"\ntemplate = \"{}\"\n",
+            STYLESHEET_PATH, TEMPLATE_PATH
+        ));
+    }
+    config.push_str(
+        "\n\
+         [database]\n\
+         path = \"leli.db\"\n\
+         \n\
+         # Languages to extract besides python and rust\n\
+         [languages]\n",
+    );
+    config
+}
+
+fn example_document(name: &str, title: &str) -> String {
+    format!(
+        "---\n\
+         output_filename: \"{name}\"\n\
+         ---\n\
+         \n\
+         # {title}\n\
+         \n\
+         Describe what the code does and why, then show it. Code blocks marked with a language\n\
+         class are extracted to `{name}.py` by `leli extract`:\n\
+         \n\
+         ```{{.python .cb-code}}\n\
+         def greet(name):\n\
+         \x20   return f\"Hello, {{name}}!\"\n\
+         ```\n\
+         \n\
+         Blocks without the class stay illustrative:\n\
+         \n\
+         ```python\n\
+         greet(\"leli\")\n\
+         ```\n",
+    )
+}
+
+/// Writes a file unless it exists, so `init` never overwrites a project's own files.
+fn create_file(path: &Path, content: &str) -> io::Result<()> {
+    if path.exists() {
+        report::emit(Event::Skipped { path: path.to_path_buf(), reason: SkipReason::Exists });
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    report::emit(Event::Created { path: path.to_path_buf() });
+    Ok(())
+}
+
+/// Adds the entries `.gitignore` is missing.
+fn update_gitignore(path: &Path) -> io::Result<()> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let missing: Vec<&str> = GITIGNORE_ENTRIES
+        .iter()
+        .copied()
+        .filter(|entry| !existing.lines().any(|line| line.trim() == *entry))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut content = existing.clone();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for entry in &missing {
+        content.push_str(entry);
+        content.push('\n');
+    }
+    fs::write(path, content)?;
+    let path = path.to_path_buf();
+    report::emit(if existing.is_empty() { Event::Created { path } } else { Event::Updated { path } });
+    Ok(())
+}
+
+/// Scaffolds a literate project in `folder`: a `leli.toml`, an example document and a
+/// `.gitignore`. With a service name, the document goes into an AImM-style
+/// `docs/<service>/{private,public}` layout; with `assets`, the default stylesheet and page
+/// template are copied into the project for customization.
+pub fn init_project(folder: &Path, service: Option<&str>, assets: bool) -> Result<(), LeliError> {
+    if let Some(service) = service {
+        check_service_name(service)?;
+    }
+    create_project(folder, service, assets).at(folder)
+}
+
+/// A service name becomes a folder name, so it must not reach outside the docs folder.
+fn check_service_name(service: &str) -> Result<(), LeliError> {
+    if service.is_empty() || service == "." || service.contains("..") || service.contains(['/', '\\']) {
+        return Err(LeliError::Usage(format!(
+            "invalid service name '{}', it must be a folder name without path separators or '..'",
+            service
+        )));
+    }
+    Ok(())
+}
+
+fn create_project(folder: &Path, service: Option<&str>, assets: bool) -> io::Result<()> {
+    fs::create_dir_all(folder)?;
+    create_file(&folder.join(CONFIG_FILE), &starter_config(service, assets))?;
+
+    let docs = folder.join(DOCS_FOLDER);
+    match service {
+        Some(service) => {
+            create_file(
+                &docs.join(service).join("private").join(format!("{}.md", service)),
+                &example_document(service, service),
+            )?;
+            create_file(
+                &docs.join(service).join("public").join("api.md"),
+                &example_document("api", &format!("{} API", service)),
+            )?;
+        }
+        None => create_file(&docs.join("example.md"), &example_document("example", "Example"))?,
+    }
+
+    if assets {
+        create_file(&folder.join(STYLESHEET_PATH), STYLE_CSS)?;
+        create_file(&folder.join(TEMPLATE_PATH), PAGE_TEMPLATE)?;
+    }
+
+    update_gitignore(&folder.join(".gitignore"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_names_must_stay_inside_the_docs_folder() {
+        let folder = std::env::temp_dir().join(format!("leli-init-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+
+        for service in ["../x", "a/b", "a\\b", "..", ".", ""] {
+            let error = init_project(&folder, Some(service), false).unwrap_err();
+            assert_eq!(error.exit_code(), 2, "{}", service);
+        }
+        assert!(!folder.exists());
+
+        init_project(&folder, Some("billing"), false).unwrap();
+        assert!(folder.join("docs/billing/public/api.md").is_file());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
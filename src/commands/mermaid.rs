@@ -0,0 +1,303 @@
+use std::fs;
+use std::io;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+use kuchikiki::NodeRef;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::MERMAID_JS;
+use crate::commands::html::{
+    escape_html, insertion_point, parse_fragment, relative_to_doc_root, select_all, Page, Transform,
+};
+
+/// Location of the shared Mermaid.js copy, relative to the doc output.
+pub const SHARED_MERMAID_PATH: &str = "js/mermaid.min.js";
+
+/// Selector of the diagram blocks Pandoc generates from mermaid code blocks.
+const MERMAID_SELECTOR: &str = "pre.mermaid";
+
+/// Counter that keeps the temporary diagram files of parallel jobs apart.
+static DIAGRAM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How the Mermaid.js script gets into pages that contain diagrams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MermaidMode {
+    /// Paste the whole script into every page, so each HTML file works on its own
+    #[default]
+    Inline,
+    /// Copy the script once into the doc output and reference it with a relative path
+    Shared,
+}
+
+fn mermaid_blocks(document: &NodeRef) -> Vec<NodeRef> {
+    select_all(document, MERMAID_SELECTOR)
+        .map(|block| block.as_node().clone())
+        .collect()
+}
+
+/// Renders every diagram to an inline SVG figure with an external renderer command.
+/// Diagrams the renderer fails on stay in place for client-side rendering.
+pub struct PrerenderDiagrams {
+    /// Command such as `mmdc -i {input} -o {output}`
+    pub renderer: String,
+}
+
+impl Transform for PrerenderDiagrams {
+    fn name(&self) -> &'static str {
+        "Mermaid pre-rendering"
+    }
+
+    fn apply(&self, document: &NodeRef, page: &mut Page) -> io::Result<()> {
+        for (index, block) in mermaid_blocks(document).into_iter().enumerate() {
+            let diagram = block.text_contents();
+            match render_mermaid_svg(&diagram, &self.renderer) {
+                Ok(svg) => {
+                    for node in parse_fragment(&svg_figure(&svg, &diagram_alt_text(&diagram))) {
+                        block.insert_before(node);
+                    }
+                    block.detach();
+                }
+                Err(e) => page.warnings.push(format!(
+                    "Diagram {} in {} is rendered client-side: {}",
+                    index + 1,
+                    page.html_path.display(),
+                    e
+                )),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the renderer command on a diagram, replacing `{input}` and `{output}` in the command
+/// with temporary `.mmd` and `.svg` files, and returns the produced SVG.
+fn render_mermaid_svg(diagram: &str, renderer: &str) -> io::Result<String> {
+    let id = DIAGRAM_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_dir = std::env::temp_dir();
+    let input_path = temp_dir.join(format!("leli-{}-{}.mmd", std::process::id(), id));
+    let output_path = input_path.with_extension("svg");
+    fs::write(&input_path, diagram)?;
+
+    let mut parts = renderer_args(renderer, &input_path.to_string_lossy(), &output_path.to_string_lossy())?
+        .into_iter();
+    let program = parts.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "empty Mermaid renderer command")
+    })?;
+    let result = Command::new(program).args(parts).output();
+    let svg = result.and_then(|output| {
+        if output.status.success() {
+            fs::read_to_string(&output_path)
+        } else {
+            Err(io::Error::other(format!(
+                "renderer {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    });
+
+    let _ = fs::remove_file(&input_path);
+    let _ = fs::remove_file(&output_path);
+    svg
+}
+
+/// Splits the renderer command into its program and arguments and fills in `{input}` and
+/// `{output}`. Words are split like a shell does: quotes keep spaces in a word, and the
+/// placeholders are filled in after splitting, so paths with spaces stay one argument.
+fn renderer_args(renderer: &str, input: &str, output: &str) -> io::Result<Vec<String>> {
+    Ok(split_command(renderer)?
+        .into_iter()
+        .map(|word| word.replace("{input}", input).replace("{output}", output))
+        .collect())
+}
+
+/// Splits a command line into words. Single quotes keep everything literally; in double quotes
+/// a backslash escapes `"` and `\`, outside of quotes any character.
+fn split_command(command: &str) -> io::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let current = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(unclosed_quote(command)),
+                    }
+                }
+            }
+            '"' => {
+                let current = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err(unclosed_quote(command)),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(unclosed_quote(command)),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn unclosed_quote(command: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("unclosed quote in Mermaid renderer command: {}", command))
+}
+
+/// Wraps a rendered SVG in a figure that carries the diagram's alt text,
+/// both as the figure's label and as the SVG's own `<title>`.
+fn svg_figure(svg: &str, alt_text: &str) -> String {
+    // Drop the XML prolog, it is not allowed inside an HTML document
+    let svg = svg.find("<svg").map_or(svg, |start| &svg[start..]).trim_end();
+    let alt_text = escape_html(alt_text);
+    let svg = match svg.find('>') {
+        Some(open_end) => format!("{}<title>{}</title>{}", &svg[..=open_end], alt_text, &svg[open_end + 1..]),
+        None => svg.to_string(),
+    };
+    format!(
+        r#"<figure class="mermaid-diagram" role="img" aria-label="{}">{}</figure>"#,
+        alt_text, svg
+    )
+}
+
+/// Uses the diagram's `accTitle` as alt text, or names the diagram type otherwise.
+fn diagram_alt_text(diagram: &str) -> String {
+    let lines = diagram
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("%%"));
+
+    let mut diagram_type = None;
+    for line in lines {
+        if let Some(title) = line.strip_prefix("accTitle:") {
+            return title.trim().to_string();
+        }
+        diagram_type.get_or_insert_with(|| line.split_whitespace().next().unwrap_or("").to_string());
+    }
+
+    match diagram_type {
+        Some(kind) if !kind.is_empty() => format!("Mermaid {} diagram", kind),
+        _ => "Mermaid diagram".to_string(),
+    }
+}
+
+/// Replaces the markup inside diagram blocks with their plain text, which is what Mermaid.js
+/// expects; Pandoc wraps the diagram source in a `<code>` element.
+pub struct CleanDiagramMarkup;
+
+impl Transform for CleanDiagramMarkup {
+    fn name(&self) -> &'static str {
+        "Mermaid cleanup"
+    }
+
+    fn apply(&self, document: &NodeRef, _page: &mut Page) -> io::Result<()> {
+        for block in mermaid_blocks(document) {
+            let diagram = block.text_contents();
+            for child in block.children().collect::<Vec<_>>() {
+                child.detach();
+            }
+            block.append(NodeRef::new_text(diagram));
+        }
+        Ok(())
+    }
+}
+
+/// Adds the Mermaid.js script to the end of the body of pages that still contain diagrams.
+pub struct InjectScript {
+    pub mode: MermaidMode,
+    /// Mermaid.js file to inline instead of the bundled copy
+    pub mermaid_path: Option<String>,
+}
+
+impl Transform for InjectScript {
+    fn name(&self) -> &'static str {
+        "Mermaid script injection"
+    }
+
+    fn apply(&self, document: &NodeRef, page: &mut Page) -> io::Result<()> {
+        if mermaid_blocks(document).is_empty() {
+            return Ok(());
+        }
+        let body = insertion_point(document, "body", page)?;
+
+        let mermaid_script = match self.mode {
+            MermaidMode::Inline => {
+                let mermaid_script_content = match &self.mermaid_path {
+                    // Read the contents of the local Mermaid.js file
+                    Some(mermaid_path) => fs::read_to_string(mermaid_path)?,
+                    None => MERMAID_JS.to_string(),
+                };
+                format!(
+                    "<script type=\"module\">\n{}\nmermaid.initialize({{ startOnLoad: true }});\n</script>",
+                    mermaid_script_content
+                )
+            }
+            MermaidMode::Shared => format!(
+                "<script src=\"{}\"></script>\n<script>mermaid.initialize({{ startOnLoad: true }});</script>",
+                relative_to_doc_root(page, SHARED_MERMAID_PATH)
+            ),
+        };
+
+        for node in parse_fragment(&mermaid_script) {
+            body.append(node);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn renderer_paths_with_spaces_stay_one_argument() {
+        let args = renderer_args(
+            "\"/opt/mermaid cli/mmdc\" -c 'my config.json' -i {input} -o {output}",
+            "/tmp/my docs/leli-1.mmd",
+            "/tmp/my docs/leli-1.svg",
+        )
+        .unwrap();
+
+        assert_eq!(
+            args,
+            words(&[
+                "/opt/mermaid cli/mmdc",
+                "-c",
+                "my config.json",
+                "-i",
+                "/tmp/my docs/leli-1.mmd",
+                "-o",
+                "/tmp/my docs/leli-1.svg",
+            ])
+        );
+    }
+
+    #[test]
+    fn split_command_handles_escapes_and_empty_words() {
+        assert_eq!(split_command(r#"a\ b "c \"d\"" '' e"#).unwrap(), words(&["a b", "c \"d\"", "", "e"]));
+        assert!(split_command("mmdc -i 'unclosed").is_err());
+    }
+}
@@ -1,43 +1,16 @@
+pub mod db;
 pub mod extract;
+pub mod graph;
+pub mod highlight;
+pub mod history;
+pub mod init;
+pub mod html;
+pub mod mermaid;
+pub mod query;
+pub mod render;
 pub mod save;
+pub mod search;
+pub mod serve;
 pub mod translate;
+pub mod watch;
 pub mod models;
-
-use clap::{Parser, Subcommand};
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    #[command(subcommand)]
-    pub command: Commands,
-}
-
-#[derive(Subcommand, Debug)]
-pub enum Commands {
-    Extract {
-        #[arg(short, long, conflicts_with = "folder")]
-        file: Option<String>,
-        #[arg(short, long, conflicts_with = "file")]
-        folder: Option<String>,
-        #[arg(short, long)]
-        output: Option<String>,
-        #[arg(short, long)]
-        protocol: Option<String>,
-    },
-    Translate {
-        #[arg(short, long)]
-        folder: String,
-        #[arg(short, long)]
-        output: Option<String>,
-        #[arg(short, long)]
-        css: Option<String>,
-        #[arg(short, long)]
-        mermaid: Option<String>,
-    },
-    Save {
-        #[arg(short, long)]
-        file: String,
-        #[arg(short, long)]
-        db: String,
-    },
-}
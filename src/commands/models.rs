@@ -1,10 +1,89 @@
 // src/commands/models.rs
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use crate::schema::html_metadata;
+use diesel::sql_types::{Nullable, Text};
+use serde::Serialize;
+use crate::schema::{code_blocks, doc_links, doc_revisions, html_metadata, save_runs};
 
 #[derive(Queryable, Insertable)]
 #[diesel(table_name = html_metadata)]
 pub struct HtmlMetadata {
     pub id: Option<i32>,
     pub file_path: String,
+    pub source_path: Option<String>,
+    pub title: Option<String>,
+    /// The YAML front matter of the source markdown, converted to JSON
+    pub front_matter: Option<String>,
+    /// SHA-256 of the HTML file
+    pub content_hash: Option<String>,
+    pub generated_at: Option<NaiveDateTime>,
+    pub leli_version: Option<String>,
+    pub service_name: Option<String>,
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[diesel(table_name = code_blocks)]
+pub struct CodeBlockMetadata {
+    pub id: Option<i32>,
+    pub document_id: i32,
+    pub language: Option<String>,
+    /// The file the block is extracted to, relative to the app folder
+    pub target_file: Option<String>,
+    pub markdown_start_line: i32,
+    pub markdown_end_line: i32,
+    pub output_start_line: Option<i32>,
+    pub output_end_line: Option<i32>,
+    /// SHA-256 of the code of the block
+    pub content_hash: String,
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[diesel(table_name = doc_links)]
+pub struct DocLink {
+    pub id: Option<i32>,
+    pub source_id: i32,
+    /// The page the reference points to
+    pub target_path: String,
+    /// `link` for a hyperlink, `chunk` for a reference to a file generated by another document
+    pub kind: String,
+    /// The generated file of a chunk reference
+    pub label: Option<String>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = save_runs)]
+pub struct SaveRun {
+    pub id: Option<i32>,
+    pub started_at: NaiveDateTime,
+    pub leli_version: Option<String>,
+    pub document_count: i32,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = doc_revisions)]
+pub struct DocRevision {
+    pub id: Option<i32>,
+    pub run_id: i32,
+    /// The `file_path` of the document
+    pub document_path: String,
+    /// SHA-256 of the HTML file
+    pub content_hash: Option<String>,
+    /// Size of the HTML file in bytes
+    pub size: i64,
+    pub generated_at: Option<NaiveDateTime>,
+    /// SHA-256 over the code of all code blocks of the document
+    pub code_hash: String,
+}
+
+/// A document matching a `leli search` query.
+#[derive(QueryableByName, Serialize)]
+pub struct SearchHit {
+    #[diesel(sql_type = Text)]
+    pub file_path: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub source_path: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub title: Option<String>,
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
 }
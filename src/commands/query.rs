@@ -0,0 +1,365 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::{Subcommand, ValueEnum};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::commands::graph::names_generated_file;
+use crate::commands::models::{CodeBlockMetadata, HtmlMetadata};
+use crate::commands::save::sha256_hex;
+use crate::schema::{code_blocks, html_metadata};
+
+/// The canned queries of `leli query`.
+#[derive(Subcommand, Debug)]
+pub enum QueryCommands {
+    /// List the saved documents
+    Docs {
+        /// Only list the documents of this service
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+    /// Count the documents of every service
+    Services,
+    /// List the files the code blocks of a document are extracted to
+    Files {
+        /// Path of the HTML page or of its markdown file
+        document: String,
+    },
+    /// Find the document a generated file comes from
+    Owner {
+        /// Path of the generated file, e.g. `.app/math_operations.py`
+        file: String,
+        /// Only show the code block that produced this line of the file
+        #[arg(short, long)]
+        line: Option<i32>,
+    },
+    /// List documents whose page or markdown changed since they were saved
+    Stale,
+}
+
+/// How `leli query` prints its results.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns for reading in a terminal
+    #[default]
+    Table,
+    /// An array with one object per row
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// The result of a query: named columns and rows of text.
+pub struct QueryResultTable {
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl QueryResultTable {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self { headers, rows: Vec::new() }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Table => self.to_text_table(),
+            OutputFormat::Json => self.to_json(),
+            OutputFormat::Csv => self.to_csv(),
+        }
+    }
+
+    fn to_text_table(&self) -> String {
+        if self.rows.is_empty() {
+            return "No rows\n".to_string();
+        }
+
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| header.len()).collect();
+        for row in &self.rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+
+        let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        let mut text = format_row(&self.headers, &widths);
+        text.push_str(&format_row(&separator, &widths));
+        for row in &self.rows {
+            text.push_str(&format_row(row, &widths));
+        }
+        text
+    }
+
+    /// The rows as objects keyed by the column names.
+    pub fn records(&self) -> Vec<serde_json::Map<String, serde_json::Value>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.headers
+                    .iter()
+                    .zip(row)
+                    .map(|(header, value)| (header.to_string(), serde_json::Value::String(value.clone())))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> String {
+        format!("{}\n", serde_json::to_string_pretty(&self.records()).unwrap_or_default())
+    }
+
+    fn to_csv(&self) -> String {
+        let mut text = format!("{}\n", self.headers.join(","));
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().map(|value| csv_field(value)).collect();
+            text.push_str(&format!("{}\n", cells.join(",")));
+        }
+        text
+    }
+}
+
+fn format_row<S: AsRef<str>>(values: &[S], widths: &[usize]) -> String {
+    let cells: Vec<String> = values
+        .iter()
+        .zip(widths)
+        .map(|(value, width)| format!("{:<width$}", value.as_ref(), width = width))
+        .collect();
+    format!("{}\n", cells.join("  ").trim_end())
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn text(value: &Option<String>) -> String {
+    value.clone().unwrap_or_default()
+}
+
+fn line_range(start: Option<i32>, end: Option<i32>) -> String {
+    match (start, end) {
+        (Some(start), Some(end)) => format!("{}-{}", start, end),
+        _ => String::new(),
+    }
+}
+
+/// Runs one of the canned queries of `leli query`.
+pub fn run_query(conn: &mut SqliteConnection, query: &QueryCommands) -> QueryResult<QueryResultTable> {
+    match query {
+        QueryCommands::Docs { service } => list_documents(conn, service.as_deref()),
+        QueryCommands::Services => list_services(conn),
+        QueryCommands::Files { document } => generated_files(conn, document),
+        QueryCommands::Owner { file, line } => file_owner(conn, file, *line),
+        QueryCommands::Stale => stale_documents(conn),
+    }
+}
+
+fn list_documents(conn: &mut SqliteConnection, service: Option<&str>) -> QueryResult<QueryResultTable> {
+    let mut query = html_metadata::table.order(html_metadata::file_path).into_boxed();
+    if let Some(service) = service {
+        query = query.filter(html_metadata::service_name.eq(service));
+    }
+    let documents: Vec<HtmlMetadata> = query.load(conn)?;
+
+    let mut table = QueryResultTable::new(vec!["file_path", "title", "service", "source_path", "generated_at"]);
+    for document in documents {
+        table.rows.push(vec![
+            document.file_path,
+            text(&document.title),
+            text(&document.service_name),
+            text(&document.source_path),
+            document.generated_at.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default(),
+        ]);
+    }
+    Ok(table)
+}
+
+fn list_services(conn: &mut SqliteConnection) -> QueryResult<QueryResultTable> {
+    let services: Vec<(Option<String>, i64)> = html_metadata::table
+        .group_by(html_metadata::service_name)
+        .select((html_metadata::service_name, count_star()))
+        .order(html_metadata::service_name)
+        .load(conn)?;
+
+    let mut table = QueryResultTable::new(vec!["service", "documents"]);
+    for (service, count) in services {
+        table.rows.push(vec![service.unwrap_or_else(|| "(none)".to_string()), count.to_string()]);
+    }
+    Ok(table)
+}
+
+/// Loads code blocks together with the path of their page and markdown file, either of all
+/// documents or of the one whose page or markdown path is `document`.
+fn code_blocks_with_documents(
+    conn: &mut SqliteConnection,
+    document: Option<&str>,
+) -> QueryResult<Vec<(CodeBlockMetadata, String, Option<String>)>> {
+    let mut query = code_blocks::table
+        .inner_join(html_metadata::table.on(html_metadata::id.eq(code_blocks::document_id.nullable())))
+        .select((
+            code_blocks::all_columns,
+            html_metadata::file_path,
+            html_metadata::source_path,
+        ))
+        .order((html_metadata::file_path, code_blocks::markdown_start_line))
+        .into_boxed();
+    if let Some(document) = document {
+        query = query.filter(
+            html_metadata::file_path
+                .eq(document)
+                .or(html_metadata::source_path.eq(document)),
+        );
+    }
+    query.load(conn)
+}
+
+fn generated_files(conn: &mut SqliteConnection, document: &str) -> QueryResult<QueryResultTable> {
+    let blocks = code_blocks_with_documents(conn, Some(document))?;
+
+    let mut table = QueryResultTable::new(vec!["target_file", "language", "markdown_lines", "output_lines"]);
+    for (block, _, _) in blocks {
+        let Some(target_file) = block.target_file else {
+            continue;
+        };
+        table.rows.push(vec![
+            target_file,
+            text(&block.language),
+            line_range(Some(block.markdown_start_line), Some(block.markdown_end_line)),
+            line_range(block.output_start_line, block.output_end_line),
+        ]);
+    }
+    Ok(table)
+}
+
+/// Finds the code blocks a generated file comes from. Target files are stored relative to the
+/// app folder, so `file` matches by its trailing path components.
+fn file_owner(conn: &mut SqliteConnection, file: &str, line: Option<i32>) -> QueryResult<QueryResultTable> {
+    let blocks = code_blocks_with_documents(conn, None)?;
+
+    let mut table = QueryResultTable::new(vec!["source_path", "file_path", "markdown_lines", "output_lines"]);
+    for (block, file_path, source_path) in blocks {
+        let Some(target_file) = &block.target_file else {
+            continue;
+        };
+        if !names_generated_file(file, target_file) {
+            continue;
+        }
+        if let Some(line) = line {
+            let contains_line = matches!(
+                (block.output_start_line, block.output_end_line),
+                (Some(start), Some(end)) if (start..=end).contains(&line)
+            );
+            if !contains_line {
+                continue;
+            }
+        }
+        table.rows.push(vec![
+            text(&source_path),
+            file_path,
+            line_range(Some(block.markdown_start_line), Some(block.markdown_end_line)),
+            line_range(block.output_start_line, block.output_end_line),
+        ]);
+    }
+    Ok(table)
+}
+
+fn modified_at(path: &Path) -> Option<NaiveDateTime> {
+    let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+    Some(DateTime::<Utc>::from(modified).naive_utc())
+}
+
+/// Lists documents that no longer match what was saved: the page was regenerated or edited
+/// since `save`, or the markdown was changed after the page was generated.
+fn stale_documents(conn: &mut SqliteConnection) -> QueryResult<QueryResultTable> {
+    let documents: Vec<HtmlMetadata> = html_metadata::table.order(html_metadata::file_path).load(conn)?;
+
+    let mut table = QueryResultTable::new(vec!["file_path", "reason"]);
+    for document in documents {
+        let mut reasons = Vec::new();
+
+        match fs::read(&document.file_path) {
+            Ok(content) => {
+                if document.content_hash.as_deref() != Some(sha256_hex(&content).as_str()) {
+                    reasons.push("page changed since save");
+                }
+            }
+            Err(_) => reasons.push("page missing"),
+        }
+
+        if let Some(source_path) = &document.source_path {
+            match modified_at(Path::new(source_path)) {
+                Some(modified) if document.generated_at.is_some_and(|generated| modified > generated) => {
+                    reasons.push("markdown newer than page")
+                }
+                Some(_) => {}
+                None => reasons.push("markdown missing"),
+            }
+        }
+
+        if !reasons.is_empty() {
+            table.rows.push(vec![document.file_path, reasons.join(", ")]);
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::save::establish_connection;
+    use crate::commands::translate::{Engine, Renderer, TranslateOptions};
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_output_quotes_only_the_fields_that_need_it() {
+        let mut table = QueryResultTable::new(vec!["file_path", "title"]);
+        table.rows.push(vec!["doc/a.html".to_string(), "Hello, \"world\"".to_string()]);
+
+        assert_eq!(table.format(OutputFormat::Csv), "file_path,title\ndoc/a.html,\"Hello, \"\"world\"\"\"\n");
+    }
+
+    const API_DOCUMENT: &str = "---\noutput_filename: api\n---\n```{.python}\ndef handle():\n    pass\n```\n";
+
+    fn owners(conn: &mut SqliteConnection, file: &str) -> Vec<String> {
+        let query = QueryCommands::Owner { file: file.to_string(), line: None };
+        let table = run_query(conn, &query).unwrap();
+        table.rows.into_iter().map(|row| row[0].clone()).collect()
+    }
+
+    #[test]
+    fn owner_tells_documents_with_the_same_file_name_apart() {
+        let root = std::env::temp_dir().join(format!("leli-owner-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let docs = root.join("docs");
+        for service in ["a", "b"] {
+            fs::create_dir_all(docs.join(service).join("public")).unwrap();
+            fs::write(docs.join(service).join("public").join("api.md"), API_DOCUMENT).unwrap();
+        }
+        let options = TranslateOptions { engine: Engine::Native, write_file_list: false, ..TranslateOptions::default() };
+        let mut conn = establish_connection(":memory:").unwrap();
+        Renderer::new(root.join("doc"), options).render_folder(&docs, Some(&mut conn)).unwrap();
+
+        let a_source = docs.join("a").join("public").join("api.md").to_string_lossy().into_owned();
+        assert_eq!(owners(&mut conn, ".app/a/public/api.py"), vec![a_source.clone()]);
+        assert_eq!(owners(&mut conn, "a/src/api.py"), vec![a_source]);
+        assert_eq!(owners(&mut conn, "api.py").len(), 2);
+        assert!(owners(&mut conn, "c/public/api.py").is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use pulldown_cmark::{html, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::highlighting::Theme;
+
+use crate::assets::PAGE_TEMPLATE;
+use crate::commands::extract::{fence_classes, strip_front_matter};
+use crate::commands::highlight::highlight_code;
+use crate::commands::html::escape_html;
+
+/// Renders a markdown file to a standalone HTML page without Pandoc.
+/// Code blocks are highlighted with `theme`; mermaid blocks get the same markup Pandoc
+/// produces, so the post-processing pipeline handles both engines alike.
+/// A template replaces the page layout; its `$title$`, `$css$` and `$body$` are filled in.
+pub fn render_markdown_to_html(
+    input_path: &Path,
+    output_path: &Path,
+    css_path: &str,
+    template_path: Option<&str>,
+    theme: &Theme,
+) -> io::Result<()> {
+    let content = fs::read_to_string(input_path)?;
+    let markdown = strip_front_matter(&content);
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+
+    let mut title: Option<String> = None;
+    let mut in_title = false;
+    let mut code_block: Option<(String, String)> = None;
+    let mut events = Vec::new();
+
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((info, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((info, code)) = code_block.take() {
+                    events.push(Event::Html(render_code_block(&info, &code, theme).into()));
+                }
+            }
+            Event::Text(text) if code_block.is_some() => {
+                if let Some((_, code)) = code_block.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) if title.is_none() => {
+                in_title = true;
+                events.push(event);
+            }
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) if in_title => {
+                in_title = false;
+                events.push(event);
+            }
+            Event::Text(ref text) | Event::Code(ref text) if in_title => {
+                title.get_or_insert_with(String::new).push_str(text);
+                events.push(event);
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+
+    let title = title.unwrap_or_else(|| {
+        input_path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string()
+    });
+    let template = match template_path {
+        Some(template_path) => fs::read_to_string(template_path)?,
+        None => PAGE_TEMPLATE.to_string(),
+    };
+    let page = fill_template(
+        &template,
+        &[("title", &escape_html(&title)), ("css", &escape_html(css_path)), ("body", &body)],
+    );
+
+    fs::write(output_path, page)
+}
+
+/// Replaces `$name$` variables in a single pass, so values that contain `$...$` stay as they are.
+fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        page.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let variable = values
+            .iter()
+            .find(|(name, _)| after.strip_prefix(name).is_some_and(|tail| tail.starts_with('$')));
+        match variable {
+            Some((name, value)) => {
+                page.push_str(value);
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                page.push('$');
+                rest = after;
+            }
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+fn render_code_block(info: &str, code: &str, theme: &Theme) -> String {
+    let (classes, _) = fence_classes(info);
+    let class_attribute = classes.join(" ");
+
+    if classes.iter().any(|class| class == "mermaid") {
+        return format!("<pre class=\"mermaid\"><code>{}</code></pre>\n", escape_html(code));
+    }
+
+    classes
+        .iter()
+        .find_map(|lang| highlight_code(code, lang, &class_attribute, theme))
+        .unwrap_or_else(|| {
+            format!(
+                "<pre class=\"{}\"><code>{}</code></pre>\n",
+                escape_html(&class_attribute),
+                escape_html(code)
+            )
+        })
+}
@@ -1,64 +1,375 @@
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
-use diesel::result::Error;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
-use std::process::Command;
-use crate::commands::models::HtmlMetadata;
-use diesel::sql_query;
-use diesel::sql_types::Text;
+use kuchikiki::traits::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::commands::extract::{Languages, LiterateDocument};
+use crate::commands::graph::{generated_file_owners, save_doc_links, PageReferences};
+use crate::commands::history::{finish_save_run, record_revision, start_save_run};
+use crate::commands::html::{meta_content, FOLDER_META, SOURCE_META, VERSION_META};
+use crate::commands::models::{CodeBlockMetadata, HtmlMetadata};
+use crate::commands::search::{index_document, remove_orphaned_entries, SearchText};
+use crate::error::{IoContext, LeliError};
+use crate::utils::report_failures;
 
-pub fn establish_connection(database_url: &str) -> SqliteConnection {
+/// The migrations under `migrations/`, compiled into the binary.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Opens the database without touching its schema.
+pub fn open_database(database_url: &str) -> Result<SqliteConnection, LeliError> {
     dotenv().ok();
-    SqliteConnection::establish(database_url)
-        .expect(&format!("Error connecting to {}", database_url))
+    let mut conn = SqliteConnection::establish(database_url)
+        .map_err(|e| LeliError::database(&format!("cannot open {}", database_url), e))?;
+    // SQLite only enforces foreign keys (and their cascading deletes) when asked to
+    diesel::sql_query("PRAGMA foreign_keys = ON")
+        .execute(&mut conn)
+        .map_err(|e| LeliError::database("cannot enable foreign keys", e))?;
+    Ok(conn)
+}
+
+/// Opens the database and brings its schema up to date.
+pub fn establish_connection(database_url: &str) -> Result<SqliteConnection, LeliError> {
+    let mut conn = open_database(database_url)?;
+    run_migrations(&mut conn)?;
+    Ok(conn)
+}
+
+/// Applies the embedded migrations that are not yet recorded in the database.
+fn run_migrations(conn: &mut SqliteConnection) -> Result<(), LeliError> {
+    let applied = conn
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| LeliError::database("migration failed", e))?;
+    for version in applied {
+        log::info!("Applied database migration {}", version);
+    }
+    Ok(())
 }
 
-#[derive(QueryableByName)]
-struct Exists {
-    #[diesel(sql_type = Text)]
-    #[allow(dead_code)]
-    name: String,
+/// Everything `save` stores about one generated page.
+pub struct DocumentRecord {
+    pub metadata: HtmlMetadata,
+    /// The code blocks of the source markdown; their `document_id` is set when saving
+    pub code_blocks: Vec<CodeBlockMetadata>,
+    /// The text indexed for `leli search`
+    pub search_text: SearchText,
+    /// The links and file references of the page, stored as edges of the document graph
+    pub references: PageReferences,
+    /// Size of the HTML file in bytes
+    pub size: i64,
 }
 
-fn table_exists(conn: &mut SqliteConnection, table_name: &str) -> bool {
-    let query = format!("SELECT name FROM sqlite_master WHERE type='table' AND name='{}';", table_name);
-    let result: Result<Option<Exists>, _> = sql_query(query).get_result(conn);
-    result.map(|res| res.is_some()).unwrap_or(false)
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
 }
 
-fn run_migrations(database_url: &str) {
-    let output = Command::new("diesel")
-        .arg("migration")
-        .arg("run")
-        .env("DATABASE_URL", database_url)
-        .output()
-        .expect("Failed to execute migration command");
-    if !output.status.success() {
-        panic!("Migration failed: {}", String::from_utf8_lossy(&output.stderr));
+/// Reads the metadata of a generated HTML page and of the markdown file it was generated from.
+/// The code blocks in `languages` are recorded with the file they are extracted to, relative to
+/// the app folder.
+pub fn read_document_metadata(html_path: &str, languages: &Languages) -> Result<DocumentRecord, LeliError> {
+    let html_content = fs::read(html_path).at(html_path)?;
+    let generated_at = fs::metadata(html_path).and_then(|metadata| metadata.modified()).at(html_path)?;
+    let document = kuchikiki::parse_html().one(String::from_utf8_lossy(&html_content).into_owned());
+
+    let title = document
+        .select_first("title")
+        .ok()
+        .map(|title| title.text_contents().trim().to_string())
+        .filter(|title| !title.is_empty());
+    let source_path = meta_content(&document, SOURCE_META);
+    let references = PageReferences::read(&document, html_path);
+
+    // The markdown may have moved since the page was generated; then only the page is indexed
+    let (literate, search_text) = match &source_path {
+        Some(source) if Path::new(source).is_file() => {
+            let markdown = fs::read_to_string(source).at(source)?;
+            (LiterateDocument::parse(&markdown, languages), SearchText::from_markdown(&markdown))
+        }
+        _ => {
+            let prose = match document.select_first("body") {
+                Ok(body) => body.text_contents(),
+                Err(()) => String::new(),
+            };
+            (LiterateDocument::default(), SearchText { prose, ..SearchText::default() })
+        }
+    };
+    let front_matter: Option<serde_yaml::Value> = literate
+        .front_matter
+        .as_deref()
+        .and_then(|meta_data| serde_yaml::from_str(meta_data).ok());
+
+    let metadata = HtmlMetadata {
+        id: None,
+        file_path: html_path.to_string(),
+        service_name: service_name(source_path.as_deref().map(Path::new), front_matter.as_ref()),
+        front_matter: front_matter.and_then(|meta| serde_json::to_string(&meta).ok()),
+        source_path,
+        title,
+        content_hash: Some(sha256_hex(&html_content)),
+        generated_at: Some(DateTime::<Utc>::from(generated_at).naive_utc()),
+        leli_version: meta_content(&document, VERSION_META),
+    };
+
+    Ok(DocumentRecord {
+        metadata,
+        code_blocks: read_code_blocks(&literate, meta_content(&document, FOLDER_META).as_deref()),
+        search_text,
+        references,
+        size: html_content.len() as i64,
+    })
+}
+
+/// `folder` is where the document's code is extracted to below the app folder; pages generated
+/// before it was recorded only know the file names.
+fn read_code_blocks(literate: &LiterateDocument, folder: Option<&str>) -> Vec<CodeBlockMetadata> {
+    let output_lines = match literate.meta() {
+        Ok(Some(meta)) => literate.output_lines(&meta),
+        _ => vec![None; literate.code_blocks.len()],
+    };
+
+    literate
+        .code_blocks
+        .iter()
+        .zip(output_lines)
+        .map(|(block, output)| CodeBlockMetadata {
+            id: None,
+            document_id: 0,
+            language: block.language.clone(),
+            target_file: output.as_ref().map(|output| match folder {
+                Some(folder) if !folder.is_empty() => format!("{}/{}", folder, output.target_file),
+                _ => output.target_file.clone(),
+            }),
+            markdown_start_line: block.start_line as i32,
+            markdown_end_line: block.end_line as i32,
+            output_start_line: output.as_ref().map(|output| output.start_line as i32),
+            output_end_line: output.as_ref().map(|output| output.end_line as i32),
+            content_hash: sha256_hex(block.content.as_bytes()),
+        })
+        .collect()
+}
+
+/// The service a document belongs to: the `service` key of its front matter or, following the
+/// AImM layout, the folder that holds the document's `private` or `public` folder.
+fn service_name(source_path: Option<&Path>, front_matter: Option<&serde_yaml::Value>) -> Option<String> {
+    if let Some(service) = front_matter.and_then(|meta| meta.get("service")).and_then(|s| s.as_str()) {
+        return Some(service.to_string());
     }
+
+    source_path?
+        .ancestors()
+        .find(|folder| folder.ends_with("private") || folder.ends_with("public"))
+        .and_then(|folder| folder.parent())
+        .and_then(|service| service.file_name())
+        .map(|service| service.to_string_lossy().into_owned())
 }
 
-pub fn save_html_metadata_to_db(html_files: &[String], conn: &mut SqliteConnection, database_url: &str) -> Result<(), Error> {
-    use crate::schema::html_metadata::dsl::*;
+/// Saves pages one by one within one save run, each in its own transaction, so the pages saved
+/// so far stay saved when a later one fails. Links between the pages are resolved by
+/// [`PageSaver::finish`], once the code blocks of all of them are stored.
+pub struct PageSaver<'a> {
+    conn: &'a mut SqliteConnection,
+    run_id: i32,
+    /// Id, path and references of every saved page
+    saved: Vec<(i32, String, PageReferences)>,
+    revisions: usize,
+}
+
+impl<'a> PageSaver<'a> {
+    pub fn start(conn: &'a mut SqliteConnection) -> Result<Self, LeliError> {
+        let run_id = start_save_run(conn).map_err(|e| LeliError::database("cannot start a save run", e))?;
+        Ok(PageSaver { conn, run_id, saved: Vec::new(), revisions: 0 })
+    }
 
-    if !table_exists(conn, "html_metadata") {
-        println!("Table 'html_metadata' does not exist. Running migrations...");
-        run_migrations(database_url);
-        // Reconnect to the database to refresh the schema
-        *conn = establish_connection(database_url);
+    /// Saves a page. A path saved before is updated in place.
+    pub fn save(&mut self, document: DocumentRecord) -> Result<(), LeliError> {
+        use crate::schema::html_metadata::dsl::*;
+        use diesel::upsert::excluded;
+
+        let run_id = self.run_id;
+        let (document_id, revised) = self.conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let revised = record_revision(conn, run_id, &document)?;
+            let new_metadata = &document.metadata;
+            diesel::insert_into(html_metadata)
+                .values(new_metadata)
+                .on_conflict(file_path)
+                .do_update()
+                .set((
+                    source_path.eq(excluded(source_path)),
+                    title.eq(excluded(title)),
+                    front_matter.eq(excluded(front_matter)),
+                    content_hash.eq(excluded(content_hash)),
+                    generated_at.eq(excluded(generated_at)),
+                    leli_version.eq(excluded(leli_version)),
+                    service_name.eq(excluded(service_name)),
+                ))
+                .execute(conn)?;
+
+            let document_id = html_metadata
+                .filter(file_path.eq(&new_metadata.file_path))
+                .select(id)
+                .first::<Option<i32>>(conn)?
+                .unwrap_or_default();
+            save_code_blocks(conn, document_id, &document.code_blocks)?;
+            index_document(conn, document_id, new_metadata.title.as_deref(), &document.search_text)?;
+            Ok((document_id, revised))
+        })?;
+
+        self.revisions += usize::from(revised);
+        self.saved.push((document_id, document.metadata.file_path, document.references));
+        Ok(())
     }
 
+    /// Resolves the links of the saved pages and closes the save run.
+    pub fn finish(self) -> Result<(), LeliError> {
+        let PageSaver { conn, run_id, saved, revisions } = self;
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let owners = generated_file_owners(conn)?;
+            for (document_id, page_path, references) in &saved {
+                save_doc_links(conn, *document_id, page_path, references, &owners)?;
+            }
+            finish_save_run(conn, run_id, saved.len())
+        })?;
+
+        log::info!("Saved HTML metadata of {} documents to database", saved.len());
+        log::info!("Recorded {} changed documents in save run {}", revisions, run_id);
+        Ok(())
+    }
+}
+
+/// Saves the metadata of the given HTML files in a single transaction. Paths saved before are
+/// updated in place; with `prune`, rows of files that are no longer listed are removed, unless
+/// no files are listed at all.
+pub fn save_html_metadata_to_db(
+    html_files: &[String],
+    conn: &mut SqliteConnection,
+    prune: bool,
+    languages: &Languages,
+) -> Result<(), LeliError> {
+    // Nothing is saved unless every page can be read, but all unreadable pages are reported
+    let mut documents = Vec::with_capacity(html_files.len());
+    let mut failures = Vec::new();
     for path in html_files {
-        let new_metadata = HtmlMetadata {
-            id: None,
-            file_path: path.clone(),
-        };
+        match read_document_metadata(path, languages) {
+            Ok(document) => documents.push(document),
+            Err(e) => failures.push((PathBuf::from(path), e)),
+        }
+    }
+    report_failures(failures, html_files.len())?;
+
+    let pruned = conn.transaction::<_, LeliError, _>(|conn| {
+        let mut saver = PageSaver::start(conn)?;
+        for document in documents {
+            saver.save(document)?;
+        }
+        saver.finish()?;
+
+        if prune && !html_files.is_empty() {
+            let pruned = prune_documents(conn, html_files)?;
+            remove_orphaned_entries(conn)?;
+            Ok(pruned)
+        } else {
+            Ok(0)
+        }
+    })?;
+
+    if prune && html_files.is_empty() {
+        log::warn!("Nothing pruned, no HTML files are listed");
+    } else if prune {
+        log::info!("Pruned {} documents that are no longer listed", pruned);
+    }
+    Ok(())
+}
+
+/// Number of rows deleted per statement, well below SQLite's limit on bound parameters.
+const DELETE_CHUNK_SIZE: usize = 500;
+
+/// Deletes the documents that are not in `html_files` and returns how many there were.
+fn prune_documents(conn: &mut SqliteConnection, html_files: &[String]) -> QueryResult<usize> {
+    use crate::schema::html_metadata::dsl::*;
 
-        diesel::insert_into(html_metadata)
-            .values(&new_metadata)
-            .execute(conn)?; // Use mutable reference
+    let listed: HashSet<&str> = html_files.iter().map(String::as_str).collect();
+    let unlisted: Vec<i32> = html_metadata
+        .select((id, file_path))
+        .load::<(Option<i32>, String)>(conn)?
+        .into_iter()
+        .filter(|(_, path)| !listed.contains(path.as_str()))
+        .filter_map(|(document_id, _)| document_id)
+        .collect();
+
+    let mut pruned = 0;
+    for chunk in unlisted.chunks(DELETE_CHUNK_SIZE) {
+        pruned += diesel::delete(html_metadata.filter(id.eq_any(chunk))).execute(conn)?;
     }
+    Ok(pruned)
+}
+
+/// Replaces the stored code blocks of a document.
+fn save_code_blocks(
+    conn: &mut SqliteConnection,
+    document_id: i32,
+    blocks: &[CodeBlockMetadata],
+) -> QueryResult<()> {
+    use crate::schema::code_blocks::dsl;
 
-    println!("Saved HTML metadata to database");
+    diesel::delete(dsl::code_blocks.filter(dsl::document_id.eq(document_id))).execute(conn)?;
+
+    let blocks: Vec<CodeBlockMetadata> = blocks
+        .iter()
+        .cloned()
+        .map(|block| CodeBlockMetadata { document_id, ..block })
+        .collect();
+    diesel::insert_into(dsl::code_blocks).values(&blocks).execute(conn)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::html_metadata;
+
+    fn database_with_documents(paths: &[&str]) -> SqliteConnection {
+        let mut conn = establish_connection(":memory:").unwrap();
+        for path in paths {
+            let document = HtmlMetadata {
+                id: None,
+                file_path: path.to_string(),
+                source_path: None,
+                title: None,
+                front_matter: None,
+                content_hash: None,
+                generated_at: None,
+                leli_version: None,
+                service_name: None,
+            };
+            diesel::insert_into(html_metadata::table).values(&document).execute(&mut conn).unwrap();
+        }
+        conn
+    }
+
+    fn saved_paths(conn: &mut SqliteConnection) -> Vec<String> {
+        html_metadata::table.select(html_metadata::file_path).order(html_metadata::file_path).load(conn).unwrap()
+    }
+
+    #[test]
+    fn prune_handles_more_files_than_sqlite_parameters() {
+        let mut conn = database_with_documents(&["doc/a.html", "doc/b.html", "doc/c.html"]);
+        let mut listed: Vec<String> = (0..40_000).map(|index| format!("doc/new_{}.html", index)).collect();
+        listed.push("doc/b.html".to_string());
+
+        assert_eq!(prune_documents(&mut conn, &listed).unwrap(), 2);
+        assert_eq!(saved_paths(&mut conn), vec!["doc/b.html"]);
+    }
+
+    #[test]
+    fn an_empty_file_list_prunes_nothing() {
+        let mut conn = database_with_documents(&["doc/a.html", "doc/b.html"]);
+
+        save_html_metadata_to_db(&[], &mut conn, true, &Languages::default()).unwrap();
+
+        assert_eq!(saved_paths(&mut conn), vec!["doc/a.html", "doc/b.html"]);
+    }
+}
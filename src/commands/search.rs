@@ -0,0 +1,170 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Double, Integer, Text};
+use diesel::sqlite::SqliteConnection;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::commands::extract::strip_front_matter;
+use crate::commands::models::SearchHit;
+use crate::error::LeliError;
+
+/// Column weights used for ranking: title, headings, prose, code.
+const RANK_WEIGHTS: [f64; 4] = [10.0, 5.0, 1.0, 1.0];
+
+/// The text of a document, split the way it is indexed for full-text search.
+#[derive(Debug, Clone, Default)]
+pub struct SearchText {
+    pub headings: String,
+    pub prose: String,
+    pub code: String,
+}
+
+impl SearchText {
+    /// Splits a markdown file into headings, prose and code.
+    pub fn from_markdown(content: &str) -> Self {
+        let mut text = SearchText::default();
+        let mut in_heading = false;
+        let mut in_code = false;
+
+        for event in Parser::new_ext(strip_front_matter(content), Options::ENABLE_TABLES) {
+            match event {
+                Event::Start(Tag::Heading { .. }) => in_heading = true,
+                Event::End(TagEnd::Heading(_)) => {
+                    in_heading = false;
+                    text.headings.push('\n');
+                }
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(TagEnd::CodeBlock) => in_code = false,
+                Event::End(TagEnd::Paragraph | TagEnd::Item) => text.prose.push('\n'),
+                Event::Text(value) | Event::Code(value) => {
+                    let target = if in_code {
+                        &mut text.code
+                    } else if in_heading {
+                        &mut text.headings
+                    } else {
+                        &mut text.prose
+                    };
+                    target.push_str(&value);
+                }
+                Event::SoftBreak | Event::HardBreak => text.prose.push(' '),
+                _ => {}
+            }
+        }
+
+        text
+    }
+}
+
+/// Replaces the indexed text of a document.
+pub fn index_document(
+    conn: &mut SqliteConnection,
+    document_id: i32,
+    title: Option<&str>,
+    text: &SearchText,
+) -> QueryResult<()> {
+    sql_query("DELETE FROM documents_fts WHERE rowid = ?")
+        .bind::<Integer, _>(document_id)
+        .execute(conn)?;
+    sql_query("INSERT INTO documents_fts (rowid, title, headings, prose, code) VALUES (?, ?, ?, ?, ?)")
+        .bind::<Integer, _>(document_id)
+        .bind::<Text, _>(title.unwrap_or_default())
+        .bind::<Text, _>(&text.headings)
+        .bind::<Text, _>(&text.prose)
+        .bind::<Text, _>(&text.code)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Drops index entries of documents that are no longer in the database.
+pub fn remove_orphaned_entries(conn: &mut SqliteConnection) -> QueryResult<usize> {
+    sql_query("DELETE FROM documents_fts WHERE rowid NOT IN (SELECT id FROM html_metadata)").execute(conn)
+}
+
+/// Runs an FTS5 query and returns the best matches first.
+/// Matching terms in the snippets are wrapped in `**`.
+pub fn search_documents(conn: &mut SqliteConnection, query: &str, limit: i64) -> QueryResult<Vec<SearchHit>> {
+    let [title, headings, prose, code] = RANK_WEIGHTS;
+    sql_query(
+        "SELECT h.file_path, h.source_path, h.title, \
+                snippet(documents_fts, -1, '**', '**', '...', 12) AS snippet \
+         FROM documents_fts JOIN html_metadata h ON h.id = documents_fts.rowid \
+         WHERE documents_fts MATCH ? \
+         ORDER BY bm25(documents_fts, ?, ?, ?, ?) LIMIT ?",
+    )
+    .bind::<Text, _>(query)
+    .bind::<Double, _>(title)
+    .bind::<Double, _>(headings)
+    .bind::<Double, _>(prose)
+    .bind::<Double, _>(code)
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+}
+
+/// Turns a failed search into an error: a query FTS5 cannot parse is a usage error, anything
+/// else a database error.
+pub fn search_error(error: diesel::result::Error) -> LeliError {
+    let message = error.to_string();
+    // FTS5 reports bad syntax as such, an open quote as an unterminated string and `name:term`
+    // with an unknown column as a missing column
+    let syntax_errors = ["fts5: syntax error", "unterminated string", "no such column"];
+    if syntax_errors.iter().any(|prefix| message.starts_with(prefix)) {
+        LeliError::Usage(format!(
+            "invalid search query: {} (quote terms that contain punctuation, e.g. '\"foo-bar\"')",
+            message
+        ))
+    } else {
+        LeliError::database("search failed", error)
+    }
+}
+
+/// Formats search results for the console.
+pub fn format_search_results(hits: &[SearchHit]) -> String {
+    if hits.is_empty() {
+        return "No documents found\n".to_string();
+    }
+
+    let mut text = String::new();
+    for (index, hit) in hits.iter().enumerate() {
+        let title = hit.title.as_deref().unwrap_or("(untitled)");
+        match &hit.source_path {
+            Some(source) => text.push_str(&format!("{}. {} - {} ({})\n", index + 1, title, hit.file_path, source)),
+            None => text.push_str(&format!("{}. {} - {}\n", index + 1, title, hit.file_path)),
+        }
+        let snippet = hit.snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+        text.push_str(&format!("   {}\n", snippet));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::save::{establish_connection, open_database};
+
+    fn failed_search(conn: &mut SqliteConnection, query: &str) -> LeliError {
+        match search_documents(conn, query, 10) {
+            Ok(_) => panic!("{} should fail", query),
+            Err(e) => search_error(e),
+        }
+    }
+
+    #[test]
+    fn query_syntax_errors_are_usage_errors() {
+        let mut conn = establish_connection(":memory:").unwrap();
+        for query in ["foo-", "\"unclosed", "nocolumn:term"] {
+            let error = failed_search(&mut conn, query);
+            assert!(matches!(error, LeliError::Usage(_)), "{}: {}", query, error);
+            assert_eq!(error.exit_code(), 2);
+        }
+        assert!(search_documents(&mut conn, "\"foo-\"", 10).is_ok());
+    }
+
+    #[test]
+    fn other_search_failures_are_database_errors() {
+        // Without migrations there is no search index
+        let mut conn = open_database(":memory:").unwrap();
+        let error = failed_search(&mut conn, "foo");
+        assert!(matches!(error, LeliError::Database(_)), "{}", error);
+        assert_eq!(error.exit_code(), 6);
+    }
+}
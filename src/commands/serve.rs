@@ -0,0 +1,263 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::commands::watch::{watch, WatchOptions};
+use crate::error::LeliError;
+
+/// Path the live-reload script polls for the number of the latest rebuild.
+const RELOAD_PATH: &str = "/__leli/reload";
+
+/// Injected into every served page. Reloads the page once the build generation changes, which
+/// needs nothing but the server itself, so the preview works offline.
+const RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var generation = null;
+  setInterval(function () {
+    fetch("/__leli/reload", { cache: "no-store" })
+      .then(function (response) { return response.text(); })
+      .then(function (current) {
+        if (generation !== null && current !== generation) { location.reload(); }
+        generation = current;
+      })
+      .catch(function () {});
+  }, 1000);
+})();
+</script>
+"#;
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decodes `%XX` escapes of a request path. Invalid escapes are kept as they are.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Maps a request path to a file below `root`, refusing paths that leave it.
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let path = request_path.split(['?', '#']).next().unwrap_or_default();
+    let relative = PathBuf::from(percent_decode(path.trim_start_matches('/')));
+    relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| root.join(relative))
+}
+
+/// Adds the live-reload script to a page, before `</body>` if it has one.
+fn inject_reload_script(page: &str) -> String {
+    match page.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &page[..index], RELOAD_SCRIPT, &page[index..]),
+        None => format!("{}{}", page, RELOAD_SCRIPT),
+    }
+}
+
+/// Lists a folder of the doc output, as it has no index page of its own.
+fn folder_listing(folder: &Path, request_path: &str) -> io::Result<String> {
+    let mut entries: Vec<(String, bool)> = fs::read_dir(folder)?
+        .filter_map(Result::ok)
+        .map(|entry| {
+            let is_dir = entry.path().is_dir();
+            (entry.file_name().to_string_lossy().into_owned(), is_dir)
+        })
+        .collect();
+    entries.sort();
+
+    let base = format!("{}/", request_path.trim_end_matches('/'));
+    let mut items = String::new();
+    if base != "/" {
+        items.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (name, is_dir) in entries {
+        let name = if is_dir { format!("{}/", name) } else { name };
+        items.push_str(&format!("<li><a href=\"{}{}\">{}</a></li>\n", base, name, name));
+    }
+    Ok(inject_reload_script(&format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+        base, base, items
+    )))
+}
+
+/// An answer to a request; the body is left out for `HEAD` requests.
+struct Response<'a> {
+    stream: &'a mut TcpStream,
+    head_only: bool,
+}
+
+impl Response<'_> {
+    fn send(&mut self, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+        write!(
+            self.stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )?;
+        if self.head_only {
+            return Ok(());
+        }
+        self.stream.write_all(body)
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, root: &Path, generation: &AtomicU64) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not needed, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, request_path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or("/"));
+    let mut response = Response { stream: &mut stream, head_only: method == "HEAD" };
+    if method != "GET" && method != "HEAD" {
+        return response.send("405 Method Not Allowed", "text/plain", b"Method not allowed\n");
+    }
+    if request_path == RELOAD_PATH {
+        let body = generation.load(Ordering::SeqCst).to_string();
+        return response.send("200 OK", "text/plain", body.as_bytes());
+    }
+
+    let Some(path) = resolve(root, request_path) else {
+        return response.send("403 Forbidden", "text/plain", b"Forbidden\n");
+    };
+    if path.is_dir() {
+        if !request_path.ends_with('/') {
+            let location = format!("{}/", request_path);
+            return write!(
+                response.stream,
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                location
+            );
+        }
+        let index = path.join("index.html");
+        if !index.is_file() {
+            let listing = folder_listing(&path, request_path)?;
+            return response.send("200 OK", content_type(&index), listing.as_bytes());
+        }
+        return serve_file(&mut response, &index);
+    }
+    if path.is_file() {
+        return serve_file(&mut response, &path);
+    }
+    response.send("404 Not Found", "text/plain", b"Not found\n")
+}
+
+fn serve_file(response: &mut Response, path: &Path) -> io::Result<()> {
+    let content = fs::read(path)?;
+    let content_type = content_type(path);
+    if content_type.starts_with("text/html") {
+        let page = inject_reload_script(&String::from_utf8_lossy(&content));
+        response.send("200 OK", content_type, page.as_bytes())
+    } else {
+        response.send("200 OK", content_type, &content)
+    }
+}
+
+/// Serves the doc output on `127.0.0.1:port` and rebuilds it like `watch`. Served pages reload
+/// themselves after every rebuild. Runs until the process is stopped.
+pub fn serve(options: &WatchOptions, port: u16) -> Result<(), LeliError> {
+    let Some(doc_folder) = options.doc_folder.clone() else {
+        return Err(LeliError::Usage("serve needs a doc folder to serve".to_string()));
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| LeliError::Usage(format!("cannot listen on port {}: {}", port, e)))?;
+    let generation = Arc::new(AtomicU64::new(0));
+
+    let server_generation = Arc::clone(&generation);
+    let address = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let root = doc_folder.clone();
+            let generation = Arc::clone(&server_generation);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &root, &generation) {
+                    log::warn!("Error answering request: {}", e);
+                }
+            });
+        }
+    });
+
+    let mut announced = false;
+    watch(options, || {
+        generation.fetch_add(1, Ordering::SeqCst);
+        if !announced {
+            log::info!("Serving {} at http://{}/", options.doc_folder.as_deref().unwrap_or(Path::new("")).display(), address);
+            announced = true;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_maps_request_paths_below_the_root() {
+        let root = Path::new("/srv/doc");
+        assert_eq!(resolve(root, "/"), Some(PathBuf::from("/srv/doc/")));
+        assert_eq!(resolve(root, "/a/b.html"), Some(root.join("a/b.html")));
+        assert_eq!(resolve(root, "/a%20b.html"), Some(root.join("a b.html")));
+        assert_eq!(resolve(root, "/page.html?x=../..#top"), Some(root.join("page.html")));
+    }
+
+    #[test]
+    fn resolve_refuses_paths_leaving_the_root() {
+        let root = Path::new("/srv/doc");
+        assert_eq!(resolve(root, "/../secret"), None);
+        assert_eq!(resolve(root, "/a/../../secret"), None);
+        assert_eq!(resolve(root, "/..%2fsecret"), None);
+        assert_eq!(resolve(root, "/%2e%2e/secret"), None);
+        assert_eq!(resolve(root, "/%2E%2E%2Fsecret"), None);
+        assert_eq!(resolve(root, "//etc/passwd"), Some(root.join("etc/passwd")));
+        assert_eq!(resolve(root, "/%2fetc/passwd"), None);
+        assert_eq!(resolve(root, "/./a.html"), None);
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(percent_decode("%e4%b8%ad"), "中");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+    }
+}
@@ -1,143 +1,362 @@
-use std::fs::{self, File};
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use regex::Regex;
 
-/// Generates HTML from a markdown file using Pandoc and saves it to the specified output path.
-/// Also injects the Mermaid.js script for rendering diagrams and removes unnecessary <code> tags.
-pub fn generate_html_from_markdown(
-    input_path: &str,
-    output_path: &str,
-    css_path: &str,
-    mermaid_js_path: &str
-) -> io::Result<()> {
-    let output = Command::new("pandoc")
-        .arg("--standalone")
-        .arg("--to=html")
-        .arg("--css")
-        .arg(css_path)
-        .arg("--output")
-        .arg(output_path)
-        .arg(input_path)
-        .output()?;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use diesel::sqlite::SqliteConnection;
 
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("pandoc error: {}", String::from_utf8_lossy(&output.stderr)),
-        ));
-    }
+use crate::assets::{write_asset, MERMAID_JS, SHARED_CSS_PATH, STYLE_CSS};
+use crate::commands::extract::{collect_sources, Languages, LiterateDocument};
+use crate::commands::highlight::resolve_theme;
+use crate::commands::html::{relative_url, AnnotateCode, Page, Pipeline, RecordProvenance, RewriteLinks};
+use crate::commands::mermaid::{
+    CleanDiagramMarkup, InjectScript, MermaidMode, PrerenderDiagrams, SHARED_MERMAID_PATH,
+};
+use crate::commands::render::render_markdown_to_html;
+use crate::commands::save::{read_document_metadata, PageSaver};
+use crate::config::DEFAULT_THEME;
+use crate::error::{IoContext, LeliError};
+use crate::report::{self, Event};
+use crate::utils::{default_jobs, report_failures, run_parallel, FileFilter};
 
-    // Inject Mermaid.js script into the generated HTML
-    inject_mermaid_script(output_path, mermaid_js_path)?;
+/// Converter that turns markdown into HTML.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Pandoc, which has to be installed separately
+    #[default]
+    Pandoc,
+    /// The built-in renderer, which highlights code blocks itself
+    Native,
+}
 
-    // Remove unnecessary <code> tags inside <pre class="mermaid">
-    clean_mermaid_code_tags(output_path)?;
+/// Settings shared by every page of a translation run.
+#[derive(Debug, Clone)]
+pub struct TranslateOptions {
+    pub engine: Engine,
+    /// Highlighting theme of the native engine: `light`, `dark` or a built-in theme name
+    pub theme: String,
+    /// Stylesheet URL of the pages; without one the bundled stylesheet is written to the doc output
+    pub css_path: Option<String>,
+    /// Stylesheet file written to the doc output instead of the bundled one when `css_path` is unset
+    pub stylesheet: Option<String>,
+    /// Page template: passed to Pandoc, or filled in by the native engine
+    pub template: Option<String>,
+    /// Mermaid.js file to use instead of the bundled copy
+    pub mermaid_path: Option<String>,
+    pub mermaid_mode: MermaidMode,
+    /// External command that renders a diagram to SVG, e.g. `mmdc -i {input} -o {output}`
+    pub mermaid_renderer: Option<String>,
+    pub jobs: usize,
+    /// Write the paths of the generated pages to `created_html_files.txt` for `save`
+    pub write_file_list: bool,
+    /// Selects the markdown files of the input folder to translate
+    pub filter: FileFilter,
+    /// Code blocks in these languages are marked with the file they are extracted to
+    pub languages: Languages,
+}
 
-    println!("Generated HTML from {} to {}", input_path, output_path);
-    Ok(())
+impl Default for TranslateOptions {
+    /// The settings of `leli translate` without flags or `leli.toml`.
+    fn default() -> Self {
+        TranslateOptions {
+            engine: Engine::default(),
+            theme: DEFAULT_THEME.to_string(),
+            css_path: None,
+            stylesheet: None,
+            template: None,
+            mermaid_path: None,
+            mermaid_mode: MermaidMode::default(),
+            mermaid_renderer: None,
+            jobs: default_jobs(),
+            write_file_list: true,
+            filter: FileFilter::default(),
+            languages: Languages::default(),
+        }
+    }
 }
 
-/// Injects the contents of the local Mermaid.js file into the HTML file.
-fn inject_mermaid_script(html_file_path: &str, mermaid_js_path: &str) -> io::Result<()> {
-    // Read the contents of the local Mermaid.js file
-    let mermaid_script_content = fs::read_to_string(mermaid_js_path)?;
-
-    // Create the script tag with the Mermaid.js content
-    let mermaid_script = format!(
-        r#"
-    <script type="module">
-    {}
-    mermaid.initialize({{ startOnLoad: true }});
-    </script>
-    "#,
-        mermaid_script_content
-    );
+/// Translates literate markdown into HTML pages in a doc folder, which is what
+/// `leli translate` does.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    doc_folder: PathBuf,
+    options: TranslateOptions,
+}
 
-    let mut html_content = fs::read_to_string(html_file_path)?;
+impl Renderer {
+    pub fn new(doc_folder: impl Into<PathBuf>, options: TranslateOptions) -> Self {
+        Renderer { doc_folder: doc_folder.into(), options }
+    }
 
-    if let Some(body_end) = html_content.find("</body>") {
-        html_content.insert_str(body_end, &mermaid_script);
+    pub fn doc_folder(&self) -> &Path {
+        &self.doc_folder
     }
 
-    let mut file = File::create(html_file_path)?;
-    file.write_all(html_content.as_bytes())?;
-    Ok(())
+    pub fn options(&self) -> &TranslateOptions {
+        &self.options
+    }
+
+    /// Renders one markdown file to a page at the root of the doc folder and returns the path of
+    /// the page with the warnings of the post-processing. The stylesheet and shared Mermaid.js
+    /// are only written by [`Renderer::render_folder`].
+    pub fn render_file(&self, markdown_path: &Path) -> Result<(PathBuf, Vec<String>), LeliError> {
+        fs::create_dir_all(&self.doc_folder).at(&self.doc_folder)?;
+        let html_path = html_output_path(markdown_path, &self.doc_folder);
+        let warnings = generate_html_from_markdown(
+            &markdown_path.to_string_lossy(),
+            &html_path.to_string_lossy(),
+            &self.doc_folder.to_string_lossy(),
+            &self.options,
+        )?;
+        Ok((html_path, warnings))
+    }
+
+    /// Renders a folder tree with its assets, optionally saving the pages to a database.
+    pub fn render_folder(&self, folder: &Path, conn: Option<&mut SqliteConnection>) -> Result<(), LeliError> {
+        translate_markdown_folder(&folder.to_string_lossy(), &self.doc_folder.to_string_lossy(), &self.options, conn)
+    }
+}
+
+/// Builds the post-processing pipeline every generated page runs through.
+fn build_pipeline(options: &TranslateOptions) -> Pipeline {
+    let mut pipeline = Pipeline::new().with(AnnotateCode);
+    if let Some(renderer) = &options.mermaid_renderer {
+        pipeline = pipeline.with(PrerenderDiagrams { renderer: renderer.clone() });
+    }
+    pipeline
+        .with(CleanDiagramMarkup)
+        .with(InjectScript {
+            mode: options.mermaid_mode,
+            mermaid_path: options.mermaid_path.clone(),
+        })
+        .with(RewriteLinks)
+        .with(RecordProvenance)
 }
 
-/// Removes the <code> tags inside <pre class="mermaid"> blocks.
-fn clean_mermaid_code_tags(html_file_path: &str) -> io::Result<()> {
-    let mut html_content = fs::read_to_string(html_file_path)?;
+/// Generates HTML from a markdown file using the selected engine and saves it to the specified
+/// output path. The page then runs through the post-processing pipeline, which annotates extracted
+/// code blocks, renders or prepares the Mermaid diagrams and rewrites links between documents.
+/// `doc_folder` is the root of the doc output, used to reference the shared Mermaid.js copy.
+/// Returns warnings about diagrams that fell back to client-side rendering.
+pub fn generate_html_from_markdown(
+    input_path: &str,
+    output_path: &str,
+    doc_folder: &str,
+    options: &TranslateOptions
+) -> Result<Vec<String>, LeliError> {
+    let css_path = match &options.css_path {
+        Some(css_path) => css_path.clone(),
+        None => relative_url(Path::new(output_path), Path::new(doc_folder), SHARED_CSS_PATH),
+    };
+    match options.engine {
+        Engine::Pandoc => run_pandoc(input_path, output_path, &css_path, options.template.as_deref())?,
+        Engine::Native => render_markdown_to_html(
+            Path::new(input_path),
+            Path::new(output_path),
+            &css_path,
+            options.template.as_deref(),
+            resolve_theme(&options.theme).map_err(|e| LeliError::Usage(e.to_string()))?,
+        )
+        .at(input_path)?,
+    }
+
+    // Pages without valid literate front matter simply get no code annotations
+    let document = LiterateDocument::read(Path::new(input_path), &options.languages).unwrap_or_default();
+    let mut page = Page {
+        html_path: Path::new(output_path),
+        source_path: Path::new(input_path),
+        doc_folder: Path::new(doc_folder),
+        meta: document.meta().ok().flatten(),
+        code_blocks: document.code_blocks,
+        warnings: Vec::new(),
+    };
+    build_pipeline(options).run(&mut page).at(output_path)?;
+
+    Ok(page.warnings)
+}
 
-    // Regular expression to match <pre class="mermaid"><code>...</code></pre> with multiline support
-    let re = Regex::new(r#"<pre class="mermaid"><code>(?s)(.*?)</code></pre>"#).unwrap();
+fn run_pandoc(input_path: &str, output_path: &str, css_path: &str, template: Option<&str>) -> Result<(), LeliError> {
+    let mut command = Command::new("pandoc");
+    command
+        .arg("--standalone")
+        .arg("--to=html")
+        .arg("--css")
+        .arg(css_path);
+    if let Some(template) = template {
+        command.arg("--template").arg(template);
+    }
+    command.arg("--output").arg(output_path).arg(input_path);
+    log::trace!("Running {:?}", command);
+    let output = command
+        .output()
+        .map_err(|e| LeliError::Tool { tool: "pandoc".to_string(), path: None, message: e.to_string() })?;
 
-    // Replace with <pre class="mermaid">...</pre>
-    html_content = re.replace_all(&html_content, r#"<pre class="mermaid">$1</pre>"#).to_string();
+    if !output.status.success() {
+        return Err(LeliError::Tool {
+            tool: "pandoc".to_string(),
+            path: Some(PathBuf::from(input_path)),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
 
-    let mut file = File::create(html_file_path)?;
-    file.write_all(html_content.as_bytes())?;
     Ok(())
 }
 
-/// Translates all markdown files in a folder to HTML, applying the specified CSS and Mermaid.js files.
+/// Translates all markdown files in a folder to HTML, applying the specified or the bundled CSS and Mermaid.js.
+/// Up to `options.jobs` Pandoc processes run at once; output order follows the folder order.
+/// With a database connection, every page is saved as soon as it is rendered, in its own
+/// transaction, so the pages rendered before a failure stay saved. Links between the pages are
+/// resolved once all of them are saved.
 pub fn translate_markdown_folder(
     folder_path: &str,
     doc_folder: &str,
-    css_path: &str,
-    mermaid_path: &str
-) -> io::Result<()> {
+    options: &TranslateOptions,
+    conn: Option<&mut SqliteConnection>
+) -> Result<(), LeliError> {
+    if options.engine == Engine::Native {
+        // Fail once up front instead of once per page
+        resolve_theme(&options.theme).map_err(|e| LeliError::Usage(e.to_string()))?;
+    }
+
+    fs::create_dir_all(doc_folder).at(doc_folder)?;
+    if options.css_path.is_none() {
+        let stylesheet = match &options.stylesheet {
+            Some(stylesheet) => fs::read_to_string(stylesheet).at(stylesheet)?,
+            None => STYLE_CSS.to_string(),
+        };
+        write_asset(Path::new(doc_folder), SHARED_CSS_PATH, &stylesheet).at(doc_folder)?;
+    }
+    if options.mermaid_mode == MermaidMode::Shared {
+        match &options.mermaid_path {
+            Some(mermaid_path) => {
+                let shared_path = PathBuf::from(doc_folder).join(SHARED_MERMAID_PATH);
+                if let Some(parent) = shared_path.parent() {
+                    fs::create_dir_all(parent).at(parent)?;
+                }
+                fs::copy(mermaid_path, &shared_path).at(mermaid_path)?;
+            }
+            None => write_asset(Path::new(doc_folder), SHARED_MERMAID_PATH, MERMAID_JS).at(doc_folder)?,
+        }
+    }
+
+    let tasks = collect_markdown_files(Path::new(folder_path), Path::new(doc_folder), &options.filter)?;
+
+    let total = tasks.len();
     let mut html_paths: Vec<String> = Vec::new();
+    let mut failures = Vec::new();
+    let mut saver = conn.map(PageSaver::start).transpose()?;
+    run_parallel(
+        tasks,
+        options.jobs,
+        |(input_path, html_output_path)| {
+            let result = generate_html_from_markdown(
+                &input_path.to_string_lossy(),
+                &html_output_path.to_string_lossy(),
+                doc_folder,
+                options
+            );
+            (input_path, html_output_path, result)
+        },
+        |(input_path, html_output_path, result)| match result {
+            Ok(warnings) => {
+                let html_path = html_output_path.to_string_lossy().into_owned();
+                if let Some(saver) = saver.as_mut() {
+                    let saved = read_document_metadata(&html_path, &options.languages)
+                        .and_then(|document| saver.save(document));
+                    if let Err(e) = saved {
+                        failures.push((input_path.clone(), e));
+                    }
+                }
+                html_paths.push(html_path);
+                report::emit(Event::Generated { source: input_path, target: html_output_path });
+                for message in warnings {
+                    report::emit(Event::Warning { message });
+                }
+            }
+            Err(e) => failures.push((input_path, e)),
+        },
+    );
 
-    translate_markdown_folder_internal(folder_path, doc_folder, css_path, mermaid_path, &mut html_paths)?;
+    if options.write_file_list {
+        // Write HTML file paths to a text file
+        let output_path = PathBuf::from(doc_folder).join("created_html_files.txt");
+        let mut file = std::fs::File::create(&output_path).at(&output_path)?;
+        for path in &html_paths {
+            writeln!(file, "{}", path).at(&output_path)?;
+        }
+    }
 
-    // Write HTML file paths to a text file
-    let output_path = PathBuf::from(doc_folder).join("created_html_files.txt");
-    let mut file = std::fs::File::create(&output_path)?;
-    for path in html_paths {
-        writeln!(file, "{}", path)?;
+    if let Some(saver) = saver {
+        saver.finish()?;
     }
 
-    Ok(())
+    report_failures(failures, total)
 }
 
-/// Internal function to handle the recursive translation of markdown files in subfolders.
-fn translate_markdown_folder_internal(
-    folder_path: &str,
-    doc_folder: &str,
-    css_path: &str,
-    mermaid_path: &str,
-    html_paths: &mut Vec<String>
-) -> io::Result<()> {
-    for entry in fs::read_dir(folder_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            let sub_doc_folder = PathBuf::from(doc_folder).join(path.file_name().unwrap());
-            fs::create_dir_all(&sub_doc_folder)?;
-            translate_markdown_folder_internal(
-                path.to_str().unwrap(),
-                sub_doc_folder.to_str().unwrap(),
-                css_path,
-                mermaid_path,
-                html_paths
-            )?;
-        } else if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("md") {
-            let base_name = path.file_stem().unwrap().to_str().unwrap();
-            let html_output_path = PathBuf::from(doc_folder).join(format!("{}_combined.html", base_name));
-            if let Err(e) = generate_html_from_markdown(
-                path.to_str().unwrap(),
-                html_output_path.to_str().unwrap(),
-                css_path,
-                mermaid_path
-            ) {
-                eprintln!("Error generating HTML for {}: {}", path.display(), e);
-            } else {
-                html_paths.push(html_output_path.to_str().unwrap().to_string());
-            }
+/// The page generated from a markdown file in the given doc folder.
+pub fn html_output_path(markdown_path: &Path, doc_folder: &Path) -> PathBuf {
+    let base_name = markdown_path.file_stem().unwrap_or_default().to_string_lossy();
+    doc_folder.join(format!("{}_combined.html", base_name))
+}
+
+/// Lists the markdown files of a folder and its subfolders with the page each is translated to,
+/// creating the matching folders in the doc output.
+fn collect_markdown_files(folder_path: &Path, doc_folder: &Path, filter: &FileFilter) -> Result<Vec<(PathBuf, PathBuf)>, LeliError> {
+    let mut sources = Vec::new();
+    collect_sources(folder_path, filter, &mut sources)?;
+
+    let mut tasks = Vec::new();
+    for path in sources {
+        if path.extension().and_then(|s| s.to_str()) != Some("md") {
+            continue;
         }
+        let relative_folder = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(folder_path).ok())
+            .unwrap_or(Path::new(""));
+        let sub_doc_folder = doc_folder.join(relative_folder);
+        fs::create_dir_all(&sub_doc_folder).at(&sub_doc_folder)?;
+        let html_output_path = html_output_path(&path, &sub_doc_folder);
+        tasks.push((path, html_output_path));
+    }
+    Ok(tasks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::save::establish_connection;
+    use crate::schema::{html_metadata, save_runs};
+    use diesel::prelude::*;
+
+    #[test]
+    fn pages_are_saved_even_when_another_page_fails() {
+        let root = std::env::temp_dir().join(format!("leli-translate-save-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let docs = root.join("docs");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("a.md"), "# A\n").unwrap();
+        // Not UTF-8, so the page cannot be rendered
+        fs::write(docs.join("b.md"), [0xff, 0xfe]).unwrap();
+        fs::write(docs.join("c.md"), "# C\n").unwrap();
+
+        let options = TranslateOptions { engine: Engine::Native, write_file_list: false, ..TranslateOptions::default() };
+        let mut conn = establish_connection(":memory:").unwrap();
+        let result = Renderer::new(root.join("doc"), options).render_folder(&docs, Some(&mut conn));
+
+        assert_eq!(result.unwrap_err().failure_count(), 1);
+        let saved: Vec<Option<String>> = html_metadata::table
+            .select(html_metadata::title)
+            .order(html_metadata::file_path)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(saved, vec![Some("A".to_string()), Some("C".to_string())]);
+        let counts: Vec<i32> = save_runs::table.select(save_runs::document_count).load(&mut conn).unwrap();
+        assert_eq!(counts, vec![2]);
+
+        fs::remove_dir_all(&root).unwrap();
     }
-    Ok(())
 }
@@ -0,0 +1,166 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use diesel::sqlite::SqliteConnection;
+use notify_debouncer_full::new_debouncer;
+use notify_debouncer_full::notify::{EventKind, RecursiveMode};
+
+use crate::commands::extract::Tangler;
+use crate::commands::save::{establish_connection, save_html_metadata_to_db};
+use crate::commands::translate::{generate_html_from_markdown, html_output_path, translate_markdown_folder, TranslateOptions};
+use crate::error::{IoContext, LeliError};
+use crate::report::{self, Event};
+use crate::utils::process_protocol_aimm;
+
+/// Time to wait for further events before rebuilding, so saving a file triggers one rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// What to rebuild when files in the watched folder change.
+pub struct WatchOptions {
+    pub folder: PathBuf,
+    /// Extract code into this folder; `None` skips extraction
+    pub app_folder: Option<PathBuf>,
+    pub protocol: Option<String>,
+    /// Translate pages into this folder; `None` skips translation
+    pub doc_folder: Option<PathBuf>,
+    pub translate: TranslateOptions,
+    /// Save the translated pages to this database; `None` skips saving
+    pub database: Option<PathBuf>,
+}
+
+impl WatchOptions {
+    /// Opens the database to save the translated pages to, reporting failures.
+    fn connection(&self) -> Option<SqliteConnection> {
+        let database = self.database.as_ref().filter(|_| self.doc_folder.is_some())?;
+        establish_connection(&database.to_string_lossy()).map_err(|e| report::emit_error(&e)).ok()
+    }
+
+    /// Extracts like `leli extract` into `folder` below the app folder.
+    fn tangler(&self, app_folder: &Path) -> Tangler {
+        Tangler::new(app_folder)
+            .with_languages(self.translate.languages.clone())
+            .with_jobs(self.translate.jobs)
+            .with_copies(true)
+    }
+
+    fn run_protocol(&self) {
+        if let (Some(app_folder), Some("AImM")) = (&self.app_folder, self.protocol.as_deref()) {
+            if let Err(e) = process_protocol_aimm(app_folder) {
+                report::emit_error(&e);
+            }
+        }
+    }
+}
+
+/// Extracts and translates the whole folder, as `extract` and `translate` do.
+pub fn build_all(options: &WatchOptions) {
+    let folder = options.folder.to_string_lossy();
+    if let Some(app_folder) = &options.app_folder {
+        if let Err(e) = options.tangler(app_folder).tangle_folder(&options.folder, &options.translate.filter) {
+            report::emit_error(&e);
+        }
+        options.run_protocol();
+    }
+    if let Some(doc_folder) = &options.doc_folder {
+        let mut conn = options.connection();
+        if let Err(e) = translate_markdown_folder(&folder, &doc_folder.to_string_lossy(), &options.translate, conn.as_mut()) {
+            report::emit_error(&e);
+        }
+    }
+}
+
+/// Re-extracts and re-translates the changed files. `changed` holds paths relative to the
+/// watched folder; deleted files are skipped and their outputs left in place.
+pub fn rebuild(options: &WatchOptions, changed: &BTreeSet<PathBuf>) {
+    let mut html_paths = Vec::new();
+    for relative in changed {
+        let path = options.folder.join(relative);
+        if !path.is_file() || !options.translate.filter.allows(&path) {
+            continue;
+        }
+        let relative_folder = relative.parent().unwrap_or(Path::new(""));
+
+        if let Some(app_folder) = &options.app_folder {
+            match options.tangler(&app_folder.join(relative_folder)).extract_file(&path) {
+                Ok(events) => events.into_iter().for_each(report::emit),
+                Err(e) => report::emit_error(&e),
+            }
+        }
+
+        let is_markdown = path.extension().and_then(|s| s.to_str()) == Some("md");
+        if let (Some(doc_folder), true) = (&options.doc_folder, is_markdown) {
+            let output_folder = doc_folder.join(relative_folder);
+            let html_path = html_output_path(&path, &output_folder);
+            let result = fs::create_dir_all(&output_folder).at(&output_folder).and_then(|()| {
+                generate_html_from_markdown(
+                    &path.to_string_lossy(),
+                    &html_path.to_string_lossy(),
+                    &doc_folder.to_string_lossy(),
+                    &options.translate,
+                )
+            });
+            match result {
+                Ok(warnings) => {
+                    html_paths.push(html_path.to_string_lossy().into_owned());
+                    report::emit(Event::Generated { source: path.clone(), target: html_path });
+                    warnings.into_iter().for_each(|message| report::emit(Event::Warning { message }));
+                }
+                Err(e) => report::emit_error(&e),
+            }
+        }
+    }
+    if let (false, Some(mut conn)) = (html_paths.is_empty(), options.connection()) {
+        if let Err(e) = save_html_metadata_to_db(&html_paths, &mut conn, false, &options.translate.languages) {
+            report::emit_error(&e);
+        }
+    }
+    options.run_protocol();
+}
+
+/// Builds everything once, then rebuilds the affected documents whenever files in the folder
+/// change, calling `on_rebuild` after each rebuild. Runs until the process is stopped.
+pub fn watch(options: &WatchOptions, mut on_rebuild: impl FnMut()) -> Result<(), LeliError> {
+    build_all(options);
+    on_rebuild();
+
+    let root = fs::canonicalize(&options.folder).at(&options.folder)?;
+    // Outputs inside the watched folder must not trigger rebuilds of themselves
+    let outputs: Vec<PathBuf> = [&options.app_folder, &options.doc_folder]
+        .into_iter()
+        .flatten()
+        .filter_map(|folder| fs::canonicalize(folder).ok())
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let watch_error = |e: notify_debouncer_full::notify::Error| LeliError::io(&options.folder, io::Error::other(e));
+    let mut debouncer = new_debouncer(DEBOUNCE, None, sender).map_err(watch_error)?;
+    debouncer.watch(&root, RecursiveMode::Recursive).map_err(watch_error)?;
+    log::info!("Watching {} for changes, press Ctrl+C to stop", options.folder.display());
+
+    for result in receiver {
+        let events = match result {
+            Ok(events) => events,
+            Err(errors) => {
+                errors.iter().for_each(|e| log::error!("Error watching files: {}", e));
+                continue;
+            }
+        };
+        let changed: BTreeSet<PathBuf> = events
+            .iter()
+            // Reading the sources while rebuilding must not count as a change
+            .filter(|event| !matches!(event.kind, EventKind::Access(_)))
+            .flat_map(|event| event.paths.iter())
+            .filter(|path| !outputs.iter().any(|output| path.starts_with(output)))
+            .filter_map(|path| path.strip_prefix(&root).ok().map(Path::to_path_buf))
+            .collect();
+        if !changed.is_empty() {
+            rebuild(options, &changed);
+            on_rebuild();
+        }
+    }
+    Ok(())
+}
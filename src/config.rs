@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::extract::Languages;
+use crate::commands::mermaid::MermaidMode;
+use crate::commands::translate::Engine;
+use crate::error::{IoContext, LeliError};
+
+/// Name of the project configuration file.
+pub const CONFIG_FILE: &str = "leli.toml";
+
+pub const DEFAULT_APP_FOLDER: &str = ".app";
+pub const DEFAULT_DOC_FOLDER: &str = "doc";
+pub const DEFAULT_THEME: &str = "light";
+
+/// Project settings from `leli.toml`. Every setting is optional; command line flags override them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Folder of literate markdown files read by `extract` and `translate`
+    pub input: Option<String>,
+    pub protocol: Option<String>,
+    /// Number of files processed in parallel
+    pub jobs: Option<usize>,
+    /// Only process files matching one of these globs, relative to the input folder
+    pub include: Vec<String>,
+    /// Skip files matching one of these globs, relative to the input folder
+    pub exclude: Vec<String>,
+    /// Also skip the files ignored by `.gitignore`, besides those in `.leliignore`
+    pub gitignore: bool,
+    pub extract: ExtractConfig,
+    pub translate: TranslateConfig,
+    pub database: DatabaseConfig,
+    /// Languages to extract besides the built-in ones, mapped to their file extension
+    pub languages: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractConfig {
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranslateConfig {
+    pub output: Option<String>,
+    pub engine: Option<Engine>,
+    pub theme: Option<String>,
+    /// Stylesheet URL put into the pages instead of the bundled stylesheet; unlike the other
+    /// paths it is relative to the pages
+    pub css: Option<String>,
+    /// Stylesheet file copied into the doc output in place of the bundled one
+    pub stylesheet: Option<String>,
+    pub template: Option<String>,
+    /// Mermaid.js file to use instead of the bundled copy
+    pub mermaid: Option<String>,
+    pub mermaid_mode: Option<MermaidMode>,
+    pub mermaid_renderer: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: Option<String>,
+}
+
+impl Config {
+    /// Looks for `leli.toml` in the working directory and its parents.
+    pub fn discover() -> Result<Option<(PathBuf, Config)>, LeliError> {
+        let cwd = env::current_dir()?;
+        for folder in cwd.ancestors() {
+            let path = folder.join(CONFIG_FILE);
+            if path.is_file() {
+                let config = Config::load(&path, folder != cwd)?;
+                return Ok(Some((path, config)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads a configuration file. With `rebase`, relative paths are resolved against the
+    /// folder of the file instead of the working directory.
+    pub fn load(path: &Path, rebase: bool) -> Result<Config, LeliError> {
+        let content = fs::read_to_string(path).at(path)?;
+        let mut config: Config = toml::from_str(&content).map_err(|e| LeliError::Parse {
+            path: path.to_path_buf(),
+            line: e.span().map(|span| content[..span.start].matches('\n').count() + 1),
+            message: e.message().to_string(),
+        })?;
+
+        if rebase {
+            let base = path.parent().unwrap_or(Path::new(""));
+            let rebase_path = |value: &mut Option<String>| {
+                if let Some(value) = value {
+                    if Path::new(value.as_str()).is_relative() {
+                        *value = base.join(value.as_str()).to_string_lossy().into_owned();
+                    }
+                }
+            };
+            rebase_path(&mut config.input);
+            rebase_path(&mut config.extract.output);
+            rebase_path(&mut config.translate.output);
+            rebase_path(&mut config.translate.stylesheet);
+            rebase_path(&mut config.translate.template);
+            rebase_path(&mut config.translate.mermaid);
+            rebase_path(&mut config.database.path);
+        }
+        Ok(config)
+    }
+
+    pub fn app_folder(&self) -> String {
+        self.extract.output.clone().unwrap_or_else(|| DEFAULT_APP_FOLDER.to_string())
+    }
+
+    pub fn doc_folder(&self) -> String {
+        self.translate.output.clone().unwrap_or_else(|| DEFAULT_DOC_FOLDER.to_string())
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.translate.engine.unwrap_or_default()
+    }
+
+    pub fn theme(&self) -> String {
+        self.translate.theme.clone().unwrap_or_else(|| DEFAULT_THEME.to_string())
+    }
+
+    /// The built-in languages and those of the `[languages]` table.
+    pub fn languages(&self) -> Languages {
+        Languages::new(self.languages.clone())
+    }
+
+    pub fn mermaid_mode(&self) -> MermaidMode {
+        self.translate.mermaid_mode.unwrap_or_default()
+    }
+
+    /// The configuration with the defaults filled in, i.e. the settings commands run with
+    /// when no flags are given. Unset `css` and `mermaid` stand for the bundled assets.
+    pub fn effective(&self) -> Config {
+        let languages = self.languages().extensions().clone();
+
+        Config {
+            extract: ExtractConfig { output: Some(self.app_folder()) },
+            translate: TranslateConfig {
+                output: Some(self.doc_folder()),
+                engine: Some(self.engine()),
+                theme: Some(self.theme()),
+                mermaid_mode: Some(self.mermaid_mode()),
+                ..self.translate.clone()
+            },
+            languages,
+            ..self.clone()
+        }
+    }
+}
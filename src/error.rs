@@ -0,0 +1,188 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can make a leli command fail. Each category has its own exit code, so
+/// scripts and CI can tell a broken document from a missing tool or database.
+#[derive(Debug)]
+pub enum LeliError {
+    /// Invalid arguments or settings
+    Usage(String),
+    /// Reading or writing a file failed
+    Io { path: Option<PathBuf>, source: io::Error },
+    /// The YAML front matter of a markdown file is invalid; `line` is counted in the file
+    Yaml { path: PathBuf, line: Option<usize>, source: serde_yaml::Error },
+    /// A file leli reads, such as `leli.toml` or a generated page, cannot be understood
+    Parse { path: PathBuf, line: Option<usize>, message: String },
+    /// The AImM folder layout could not be combined
+    Protocol { path: PathBuf, message: String },
+    /// Opening, migrating or querying the database failed
+    Database(String),
+    /// An external program such as Pandoc is missing or failed
+    Tool { tool: String, path: Option<PathBuf>, message: String },
+    /// Some files of a folder failed while the others were processed
+    Failures { total: usize, failures: Vec<(PathBuf, LeliError)> },
+}
+
+impl LeliError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        LeliError::Io { path: Some(path.into()), source }
+    }
+
+    pub fn database(context: &str, error: impl fmt::Display) -> Self {
+        LeliError::Database(format!("{}: {}", context, error))
+    }
+
+    /// The file the error is about, if it names one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            LeliError::Io { path, .. } | LeliError::Tool { path, .. } => path.as_deref(),
+            LeliError::Yaml { path, .. } | LeliError::Parse { path, .. } | LeliError::Protocol { path, .. } => {
+                Some(path)
+            }
+            LeliError::Usage(_) | LeliError::Database(_) | LeliError::Failures { .. } => None,
+        }
+    }
+
+    /// Number of failed files the error stands for.
+    pub fn failure_count(&self) -> usize {
+        match self {
+            LeliError::Failures { failures, .. } => failures.len(),
+            _ => 1,
+        }
+    }
+
+    /// The process exit code of the error category. A run with failed files exits with the
+    /// code of its first failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LeliError::Usage(_) => 2,
+            LeliError::Io { .. } => 3,
+            LeliError::Yaml { .. } | LeliError::Parse { .. } => 4,
+            LeliError::Protocol { .. } => 5,
+            LeliError::Database(_) => 6,
+            LeliError::Tool { .. } => 7,
+            LeliError::Failures { failures, .. } => failures.first().map_or(1, |(_, e)| e.exit_code()),
+        }
+    }
+}
+
+/// Formats `path` and an optional line as `path:line`.
+fn location(path: &Path, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{}", path.display(), line),
+        None => path.display().to_string(),
+    }
+}
+
+impl fmt::Display for LeliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeliError::Usage(message) => write!(f, "{}", message),
+            LeliError::Io { path: Some(path), source } => write!(f, "{}: {}", path.display(), source),
+            LeliError::Io { path: None, source } => write!(f, "{}", source),
+            LeliError::Yaml { path, line, source } => {
+                write!(f, "{}: invalid front matter: {}", location(path, *line), source)
+            }
+            LeliError::Parse { path, line, message } => write!(f, "{}: {}", location(path, *line), message),
+            LeliError::Protocol { path, message } => write!(f, "AImM protocol, {}: {}", path.display(), message),
+            LeliError::Database(message) => write!(f, "database: {}", message),
+            LeliError::Tool { tool, path: Some(path), message } => {
+                write!(f, "{} failed on {}: {}", tool, path.display(), message)
+            }
+            LeliError::Tool { tool, path: None, message } => write!(f, "{}: {}", tool, message),
+            LeliError::Failures { total, failures } => {
+                write!(f, "{} of {} files failed:", failures.len(), total)?;
+                for (path, error) in failures {
+                    // The path is part of most errors already
+                    match error {
+                        LeliError::Io { path: None, .. } | LeliError::Usage(_) | LeliError::Database(_) => {
+                            write!(f, "\n  {}: {}", path.display(), error)?
+                        }
+                        _ => write!(f, "\n  {}", error)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LeliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LeliError::Io { source, .. } => Some(source),
+            LeliError::Yaml { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LeliError {
+    fn from(source: io::Error) -> Self {
+        LeliError::Io { path: None, source }
+    }
+}
+
+impl From<diesel::result::Error> for LeliError {
+    fn from(error: diesel::result::Error) -> Self {
+        LeliError::Database(error.to_string())
+    }
+}
+
+/// Adds the path an IO operation worked on to its error.
+pub trait IoContext<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T, LeliError>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T, LeliError> {
+        self.map_err(|source| LeliError::io(path.as_ref(), source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "missing")
+    }
+
+    fn yaml_error() -> serde_yaml::Error {
+        serde_yaml::from_str::<usize>("[").unwrap_err()
+    }
+
+    #[test]
+    fn every_error_kind_has_its_exit_code() {
+        let path = PathBuf::from("docs/a.md");
+        let cases = [
+            (LeliError::Usage("no database given".to_string()), 2),
+            (LeliError::io(&path, io_error()), 3),
+            (LeliError::from(io_error()), 3),
+            (LeliError::Yaml { path: path.clone(), line: Some(2), source: yaml_error() }, 4),
+            (LeliError::Parse { path: path.clone(), line: None, message: "bad".to_string() }, 4),
+            (LeliError::Protocol { path: path.clone(), message: "bad".to_string() }, 5),
+            (LeliError::database("cannot open", "locked"), 6),
+            (LeliError::from(diesel::result::Error::NotFound), 6),
+            (LeliError::Tool { tool: "pandoc".to_string(), path: None, message: "missing".to_string() }, 7),
+        ];
+        for (error, exit_code) in cases {
+            assert_eq!(error.exit_code(), exit_code, "{}", error);
+        }
+    }
+
+    #[test]
+    fn failures_exit_with_the_code_of_the_first_failure() {
+        let failures = LeliError::Failures {
+            total: 3,
+            failures: vec![
+                (PathBuf::from("a.md"), LeliError::Protocol { path: PathBuf::from("a"), message: "bad".to_string() }),
+                (PathBuf::from("b.md"), LeliError::from(io_error())),
+            ],
+        };
+        assert_eq!(failures.exit_code(), 5);
+        assert_eq!(failures.failure_count(), 2);
+        assert_eq!(LeliError::Failures { total: 0, failures: Vec::new() }.exit_code(), 1);
+    }
+}
@@ -0,0 +1,41 @@
+//! Literate programming for AImM services: extract the code blocks of markdown documents into
+//! source files, translate the documents into HTML pages and keep their metadata in SQLite.
+//!
+//! The `leli` command line tool is a thin wrapper around this crate. The main entry points are
+//! [`LiterateDocument`] to parse a markdown file, [`Tangler`] to extract its code,
+//! [`Renderer`] to translate it, [`process_protocol_aimm`] to combine the AImM folder layout
+//! and [`establish_connection`] with [`save_html_metadata_to_db`] for the database.
+//! [`build::tangle`] extracts literate Rust from a `build.rs`.
+//! Progress is reported as [`report::Event`]s and through the `log` crate, so a library user
+//! sees it with any logger installed.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use leli::{FileFilter, Tangler};
+//!
+//! let filter = FileFilter::new(Path::new("docs"), &[], &[], false)?;
+//! let files = Tangler::new(".app").tangle_folder(Path::new("docs"), &filter)?;
+//! println!("extracted {} files", files.len());
+//! # Ok::<(), leli::LeliError>(())
+//! ```
+
+pub mod assets;
+pub mod build;
+/// The implementation of the `leli` commands. Only what is re-exported at the crate root is
+/// part of the API.
+#[doc(hidden)]
+pub mod commands;
+pub mod config;
+pub mod error;
+pub mod report;
+pub(crate) mod schema;
+#[doc(hidden)]
+pub mod utils;
+
+pub use commands::extract::{CodeBlock, Languages, LiterateDocument, MarkdownMeta, OutputLines, Tangler};
+pub use commands::mermaid::MermaidMode;
+pub use commands::save::{establish_connection, open_database, save_html_metadata_to_db};
+pub use commands::translate::{Engine, Renderer, TranslateOptions};
+pub use config::Config;
+pub use error::LeliError;
+pub use utils::{process_protocol_aimm, FileFilter};
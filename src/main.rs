@@ -1,17 +1,126 @@
 use clap::Parser;
-use std::fs::{self, File};
-use std::io::Write;
+use serde::Serialize;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-mod commands;
-mod utils;
-mod schema;
+mod cli;
 
-use commands::{extract::*, save::*, translate::*, Args, Commands};
-use utils::{ensure_pandoc_installed, process_protocol_aimm};
+use cli::{Args, Commands, ConfigCommands, DbCommands, FilterArgs};
+use leli::commands::{db::*, graph::*, history::*, init::*, query::*, save::*, search::*, serve::*, translate::*, watch::*};
+use leli::config::{Config, CONFIG_FILE};
+use leli::error::{IoContext, LeliError};
+use leli::report;
+use leli::Tangler;
+use leli::utils::{default_jobs, ensure_pandoc_installed, process_protocol_aimm, FileFilter};
+
+/// `leli config show` in JSON mode: the settings and the file they come from.
+#[derive(Serialize)]
+struct ShownConfig {
+    path: Option<PathBuf>,
+    #[serde(flatten)]
+    settings: Config,
+}
+
+/// The database given on the command line or in `leli.toml`.
+fn database_path(db: &Option<String>, config: &Config) -> Result<String, LeliError> {
+    db.clone().or_else(|| config.database.path.clone()).ok_or_else(|| {
+        LeliError::Usage(format!(
+            "no database given, pass --db or set `path` in the [database] section of {}",
+            CONFIG_FILE
+        ))
+    })
+}
+
+/// The folder given on the command line or the `input` of `leli.toml`.
+fn input_folder(folder: &Option<String>, config: &Config) -> Result<String, LeliError> {
+    folder.clone().or_else(|| config.input.clone()).ok_or_else(|| {
+        LeliError::Usage(format!("no input folder given, pass --folder or set `input` in {}", CONFIG_FILE))
+    })
+}
+
+/// Selects the files of `folder` by the globs given on the command line, or else those of
+/// `leli.toml`, and by the ignore files.
+fn file_filter(folder: &str, config: &Config, args: &FilterArgs) -> Result<FileFilter, LeliError> {
+    let include = if args.include.is_empty() { &config.include } else { &args.include };
+    let exclude = if args.exclude.is_empty() { &config.exclude } else { &args.exclude };
+    FileFilter::new(Path::new(folder), include, exclude, args.gitignore || config.gitignore)
+        .map_err(|e| LeliError::Usage(format!("cannot select the files of {}: {}", folder, e)))
+}
+
+/// The translate settings of `leli.toml` for the given input folder.
+fn translate_options(folder: &str, config: &Config, filter: &FilterArgs) -> Result<TranslateOptions, LeliError> {
+    Ok(TranslateOptions {
+        engine: config.engine(),
+        theme: config.theme(),
+        css_path: config.translate.css.clone(),
+        stylesheet: config.translate.stylesheet.clone(),
+        template: config.translate.template.clone(),
+        mermaid_path: config.translate.mermaid.clone(),
+        mermaid_mode: config.mermaid_mode(),
+        mermaid_renderer: config.translate.mermaid_renderer.clone(),
+        jobs: config.jobs.unwrap_or_else(default_jobs),
+        write_file_list: true,
+        filter: file_filter(folder, config, filter)?,
+        languages: config.languages(),
+    })
+}
+
+/// What `watch` and `serve` rebuild, from the flags and `leli.toml`.
+fn watch_options(
+    folder: &Option<String>,
+    extract: bool,
+    translate: bool,
+    engine: Option<Engine>,
+    filter: &FilterArgs,
+    config: &Config,
+) -> Result<WatchOptions, LeliError> {
+    let folder = input_folder(folder, config)?;
+    let mut options = translate_options(&folder, config, filter)?;
+    options.engine = engine.unwrap_or(options.engine);
+    if translate {
+        require_engine(options.engine)?;
+    }
+
+    Ok(WatchOptions {
+        folder: PathBuf::from(&folder),
+        app_folder: extract.then(|| PathBuf::from(config.app_folder())),
+        protocol: config.protocol.clone(),
+        doc_folder: translate.then(|| PathBuf::from(config.doc_folder())),
+        translate: options,
+        database: config.database.path.as_ref().map(PathBuf::from),
+    })
+}
+
+/// Fails if the selected engine cannot run.
+fn require_engine(engine: Engine) -> Result<(), LeliError> {
+    if engine == Engine::Pandoc && !ensure_pandoc_installed() {
+        return Err(LeliError::Tool {
+            tool: "pandoc".to_string(),
+            path: None,
+            message: "not installed, install Pandoc or use the native engine (--engine native)".to_string(),
+        });
+    }
+    Ok(())
+}
 
 fn main() {
     let args = Args::parse();
+    report::init(args.output_format, args.quiet, args.verbose);
+    let exit_code = report::finish(&run(&args));
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+fn run(args: &Args) -> Result<(), LeliError> {
+    let (config_path, config) = match Config::discover()? {
+        Some((path, config)) => (Some(path), config),
+        None => (None, Config::default()),
+    };
+    match &config_path {
+        Some(path) => log::debug!("Using settings from {}", path.display()),
+        None => log::debug!("No {} found, using the defaults", CONFIG_FILE),
+    }
 
     match &args.command {
         Commands::Extract {
@@ -19,82 +128,177 @@ fn main() {
             folder,
             output,
             protocol,
+            jobs,
+            filter,
         } => {
-            let app_folder = output.clone().unwrap_or_else(|| ".app".to_string());
-
-            if let Some(file) = file {
-                match extract_code_from_markdown(file) {
-                    Ok(Ok(extracted_code)) => {
-                        for (filename, code) in extracted_code {
-                            let output_path = PathBuf::from(&app_folder).join(filename);
-                            if let Some(parent) = output_path.parent() {
-                                fs::create_dir_all(parent).unwrap();
-                            }
-                            let mut output_file = File::create(&output_path).unwrap();
-                            output_file.write_all(code.as_bytes()).unwrap();
-                            println!("Code extracted to {}", output_path.display());
-                        }
-                    }
-                    Ok(Err(_)) => {
-                        // Copy simple markdown file to .app folder
-                        let output_path =
-                            PathBuf::from(&app_folder).join(Path::new(file).file_name().unwrap());
-                        fs::copy(file, &output_path).unwrap();
-                        println!("Copied file to {}", output_path.display());
-                    }
-                    Err(e) => {
-                        eprintln!("Error extracting code: {}", e);
-                    }
+            let app_folder = output.clone().unwrap_or_else(|| config.app_folder());
+            // Without a file or folder, extract the configured input folder
+            let folder = folder.clone().or_else(|| file.is_none().then(|| config.input.clone()).flatten());
+            let protocol = protocol.clone().or_else(|| config.protocol.clone());
+
+            let tangler = Tangler::new(&app_folder)
+                .with_languages(config.languages())
+                .with_jobs(jobs.or(config.jobs).unwrap_or_else(default_jobs))
+                .with_copies(true);
+            let extracted = if let Some(file) = file {
+                tangler.extract_file(Path::new(file)).map(|events| events.into_iter().for_each(report::emit))
+            } else if let Some(folder) = &folder {
+                tangler.tangle_folder(Path::new(folder), &file_filter(folder, &config, filter)?).map(|_| ())
+            } else {
+                Ok(())
+            };
+
+            // The protocol also combines what was extracted when some files failed
+            let processed = match protocol.as_deref() {
+                Some("AImM") => {
+                    log::info!("Protocol AImM detected. Combining folders...");
+                    process_protocol_aimm(&PathBuf::from(&app_folder))
                 }
-            } else if let Some(folder) = folder {
-                if let Err(e) = extract_code_from_folder(folder, &app_folder) {
-                    eprintln!("Error extracting code: {}", e);
+                Some(_) => {
+                    log::warn!("Protocol detected but not AImM.");
+                    Ok(())
                 }
-            }
-
-            if let Some(protocol) = protocol {
-                if protocol == "AImM" {
-                    println!("Protocol AImM detected. Combining folders...");
-                    if let Err(e) = process_protocol_aimm(&PathBuf::from(&app_folder)) {
-                        eprintln!("Error processing protocol AImM: {}", e);
-                    }
-                } else {
-                    println!("Protocol detected but not AImM.");
+                None => {
+                    log::debug!("No protocol specified.");
+                    Ok(())
                 }
-            } else {
-                println!("No protocol specified.");
-            }
+            };
+            extracted.and(processed)
         }
         Commands::Translate {
             folder,
+            engine,
+            theme,
             output,
             css,
+            stylesheet,
+            template,
             mermaid,
+            mermaid_mode,
+            mermaid_renderer,
+            jobs,
+            db,
+            no_file_list,
+            filter,
         } => {
-            let doc_folder = output.clone().unwrap_or_else(|| "doc".to_string());
-            let css_path = css.clone().unwrap_or_else(|| "src/css/style.css".to_string());
-            let mermaid_path = mermaid.clone().unwrap_or_else(|| "src/js/mermaid.min.js".to_string());
-
-            if !ensure_pandoc_installed() {
-                eprintln!("Pandoc is not installed. Please install Pandoc to use this tool.");
-                std::process::exit(1);
-            }
-
-            if let Err(e) = translate_markdown_folder(&folder, &doc_folder, &css_path, &mermaid_path) {
-                eprintln!("Error translating markdown: {}", e);
-            }
+            let folder = input_folder(folder, &config)?;
+            let doc_folder = output.clone().unwrap_or_else(|| config.doc_folder());
+            let configured = translate_options(&folder, &config, filter)?;
+            let options = TranslateOptions {
+                engine: engine.unwrap_or(configured.engine),
+                theme: theme.clone().unwrap_or(configured.theme),
+                css_path: css.clone().or(configured.css_path),
+                stylesheet: stylesheet.clone().or(configured.stylesheet),
+                template: template.clone().or(configured.template),
+                mermaid_path: mermaid.clone().or(configured.mermaid_path),
+                mermaid_mode: mermaid_mode.unwrap_or(configured.mermaid_mode),
+                mermaid_renderer: mermaid_renderer.clone().or(configured.mermaid_renderer),
+                jobs: jobs.unwrap_or(configured.jobs),
+                write_file_list: !*no_file_list,
+                filter: configured.filter,
+                languages: configured.languages,
+            };
+            require_engine(options.engine)?;
+            let mut conn = db.as_deref().map(establish_connection).transpose()?;
+            translate_markdown_folder(&folder, &doc_folder, &options, conn.as_mut())
         }
         Commands::Save {
             file,
             db,
+            prune,
         } => {
-            let created_files = fs::read_to_string(file).expect("Unable to read created files list");
-            let html_files: Vec<String> = created_files.lines().map(|s| s.to_string()).collect();
+            let created_files = fs::read_to_string(file).at(file)?;
+            let html_files: Vec<String> = created_files
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect();
 
-            let mut conn = establish_connection(db); // Pass the database URL
-            if let Err(e) = save_html_metadata_to_db(&html_files, &mut conn, db) {
-                eprintln!("Error saving HTML metadata to database: {}", e);
+            let mut conn = establish_connection(&database_path(db, &config)?)?;
+            save_html_metadata_to_db(&html_files, &mut conn, *prune, &config.languages())
+        }
+        Commands::Search { query, db, limit } => {
+            let mut conn = establish_connection(&database_path(db, &config)?)?;
+            let hits = search_documents(&mut conn, query, *limit).map_err(search_error)?;
+            report::results(&hits, || format_search_results(&hits));
+            Ok(())
+        }
+        Commands::Query { query, db, format } => {
+            let mut conn = establish_connection(&database_path(db, &config)?)?;
+            let table = run_query(&mut conn, query)?;
+            report::results(&table.records(), || table.format(*format));
+            Ok(())
+        }
+        Commands::Graph { db, format, output } => {
+            let mut conn = establish_connection(&database_path(db, &config)?)?;
+            let graph = load_graph(&mut conn)?;
+            let content = graph.format(*format);
+            match output {
+                Some(output) => fs::write(output, content).at(output)?,
+                None => report::results(&[graph.json()], || content),
+            }
+            for orphan in graph.orphans() {
+                log::warn!("Orphaned page (no other page refers to it): {}", orphan.path);
             }
+            Ok(())
+        }
+        Commands::History { document, db, format } => {
+            let mut conn = establish_connection(&database_path(db, &config)?)?;
+            let table = document_history(&mut conn, document)?;
+            report::results(&table.records(), || table.format(*format));
+            Ok(())
+        }
+        Commands::Db { command, db } => {
+            let db = database_path(db, &config)?;
+            if let DbCommands::Reset { yes: false } = command {
+                return Err(LeliError::Usage(format!(
+                    "this deletes all data saved in {}, run again with --yes to confirm",
+                    db
+                )));
+            }
+            let mut conn = open_database(&db)?;
+            match command {
+                DbCommands::Status => migration_status(&mut conn)
+                    .map(|states| report::results(&states, || format_migration_status(&states))),
+                DbCommands::Migrate => migrate(&mut conn),
+                DbCommands::Reset { .. } => reset(&mut conn),
+            }
+            .map_err(|e| LeliError::database("migration failed", e))
+        }
+        Commands::Init { folder, service, assets } => {
+            init_project(Path::new(folder), service.as_deref(), *assets)
+        }
+        Commands::Watch {
+            folder,
+            no_extract,
+            no_translate,
+            engine,
+            filter,
+        } => {
+            let options = watch_options(folder, !*no_extract, !*no_translate, *engine, filter, &config)?;
+            watch(&options, || {})
+        }
+        Commands::Serve {
+            folder,
+            port,
+            no_extract,
+            engine,
+            filter,
+        } => {
+            let options = watch_options(folder, !*no_extract, true, *engine, filter, &config)?;
+            serve(&options, *port)
+        }
+        Commands::Config { command: ConfigCommands::Show } => {
+            let settings = config.effective();
+            let text = toml::to_string(&settings)
+                .map_err(|e| LeliError::Usage(format!("cannot print the settings: {}", e)))?;
+            let header = match &config_path {
+                Some(path) => format!("# Settings from {}\n", path.display()),
+                None => format!("# No {} found, using the defaults\n", CONFIG_FILE),
+            };
+            report::results(&[ShownConfig { path: config_path.clone(), settings }], || header + &text);
+            Ok(())
         }
     }
 }
@@ -0,0 +1,294 @@
+//! Progress reporting. Commands describe what they did as [`Event`]s and other messages through
+//! the `log` facade; the `leli` binary prints both as text or as one JSON object per line.
+//! Without [`init`], as in a library, events only reach the logger the application installed.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::error::LeliError;
+
+/// How progress is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputMode {
+    /// Messages for people; errors and warnings go to stderr
+    #[default]
+    Text,
+    /// One JSON object per line on stdout, for scripts
+    Json,
+}
+
+static MODE: OnceLock<OutputMode> = OnceLock::new();
+
+fn mode() -> OutputMode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+/// Set by `--quiet`, which also hides the results of commands such as `search`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set once stdout is closed, e.g. by `leli ... | head`; later output is dropped.
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Writes to stdout. Unlike `println!`, a closed pipe ends the output instead of panicking.
+fn write_stdout(args: fmt::Arguments) {
+    if STDOUT_CLOSED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Err(e) = io::stdout().lock().write_fmt(args) {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            STDOUT_CLOSED.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Why a file was not processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Excluded by a glob or an ignore file
+    Ignored,
+    /// `init` does not overwrite files
+    Exists,
+}
+
+/// Something a command did to a file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Code blocks of `source` were written to `target`
+    Extracted { source: PathBuf, target: PathBuf },
+    Copied { source: PathBuf, target: PathBuf },
+    /// The page `target` was generated from `source`
+    Generated { source: PathBuf, target: PathBuf },
+    Created { path: PathBuf },
+    Updated { path: PathBuf },
+    Skipped { path: PathBuf, reason: SkipReason },
+    Warning { message: String },
+    Error { path: Option<PathBuf>, message: String, exit_code: i32 },
+}
+
+impl Event {
+    fn level(&self) -> Level {
+        match self {
+            Event::Skipped { reason: SkipReason::Ignored, .. } => Level::Debug,
+            Event::Warning { .. } => Level::Warn,
+            Event::Error { .. } => Level::Error,
+            _ => Level::Info,
+        }
+    }
+
+    fn counter(&self) -> &'static AtomicUsize {
+        let index = match self {
+            Event::Extracted { .. } => 0,
+            Event::Copied { .. } => 1,
+            Event::Generated { .. } => 2,
+            Event::Created { .. } | Event::Updated { .. } => 3,
+            Event::Skipped { .. } => 4,
+            Event::Warning { .. } => 5,
+            Event::Error { .. } => 6,
+        };
+        &COUNTS[index]
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Extracted { target, .. } => write!(f, "Code extracted to {}", target.display()),
+            Event::Copied { target, .. } => write!(f, "Copied file to {}", target.display()),
+            Event::Generated { source, target } => {
+                write!(f, "Generated HTML from {} to {}", source.display(), target.display())
+            }
+            Event::Created { path } => write!(f, "Created {}", path.display()),
+            Event::Updated { path } => write!(f, "Updated {}", path.display()),
+            Event::Skipped { path, reason: SkipReason::Ignored } => write!(f, "Skipped {}, it is ignored", path.display()),
+            Event::Skipped { path, reason: SkipReason::Exists } => {
+                write!(f, "Skipped {}, it already exists", path.display())
+            }
+            Event::Warning { message } => write!(f, "{}", message),
+            Event::Error { message, .. } => write!(f, "Error: {}", message),
+        }
+    }
+}
+
+/// Number of events of each kind, in the order of [`Event::counter`], then the results.
+static COUNTS: [AtomicUsize; 8] = [const { AtomicUsize::new(0) }; 8];
+
+/// Reports an event, if the verbosity lets its level through.
+pub fn emit(event: Event) {
+    event.counter().fetch_add(1, Ordering::Relaxed);
+    let level = event.level();
+    if level > log::max_level() {
+        return;
+    }
+    match mode() {
+        OutputMode::Json => print_json(&event),
+        OutputMode::Text => log::log!(level, "{}", event),
+    }
+}
+
+/// Reports an error as one error event per failed file.
+pub fn emit_error(error: &LeliError) {
+    match error {
+        LeliError::Failures { failures, .. } => failures.iter().for_each(|(path, error)| {
+            emit(Event::Error {
+                path: Some(error.path().unwrap_or(path).to_path_buf()),
+                message: error.to_string(),
+                exit_code: error.exit_code(),
+            })
+        }),
+        error => emit(Event::Error {
+            path: error.path().map(Path::to_path_buf),
+            message: error.to_string(),
+            exit_code: error.exit_code(),
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct ResultLine<'a, T> {
+    event: &'static str,
+    #[serde(flatten)]
+    item: &'a T,
+}
+
+/// Prints the results of a command that reports data rather than files, such as `search` or
+/// `query`: `text()` in text mode, one `{"event":"result", ...}` line per item in JSON mode.
+/// Items must serialize to objects, whose fields become those of the line. `--quiet` hides them.
+pub fn results<T: Serialize>(items: &[T], text: impl FnOnce() -> String) {
+    COUNTS[7].fetch_add(items.len(), Ordering::Relaxed);
+    if QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+    match mode() {
+        OutputMode::Json => items.iter().for_each(|item| print_json(&ResultLine { event: "result", item })),
+        OutputMode::Text => write_stdout(format_args!("{}", text())),
+    }
+}
+
+fn print_json(value: &impl Serialize) {
+    if let Ok(line) = serde_json::to_string(value) {
+        write_stdout(format_args!("{}\n", line));
+    }
+}
+
+#[derive(Serialize)]
+struct Summary {
+    event: &'static str,
+    extracted: usize,
+    copied: usize,
+    generated: usize,
+    created: usize,
+    skipped: usize,
+    warnings: usize,
+    errors: usize,
+    results: usize,
+    exit_code: i32,
+}
+
+/// Reports the outcome of a run and returns its exit code. Errors are always reported; the
+/// summary is printed in JSON mode, and with `-v` as text.
+pub fn finish(result: &Result<(), LeliError>) -> i32 {
+    let exit_code = result.as_ref().err().map_or(0, LeliError::exit_code);
+    match (mode(), result) {
+        (OutputMode::Json, Err(e)) => emit_error(e),
+        (OutputMode::Text, Err(e)) => {
+            // Counted, but printed as one message that lists all failed files
+            COUNTS[6].fetch_add(e.failure_count(), Ordering::Relaxed);
+            log::error!("Error: {}", e);
+        }
+        (_, Ok(())) => {}
+    }
+
+    let count = |index: usize| COUNTS[index].load(Ordering::Relaxed);
+    let summary = Summary {
+        event: "summary",
+        extracted: count(0),
+        copied: count(1),
+        generated: count(2),
+        created: count(3),
+        skipped: count(4),
+        warnings: count(5),
+        errors: count(6),
+        results: count(7),
+        exit_code,
+    };
+    match mode() {
+        OutputMode::Json => print_json(&summary),
+        OutputMode::Text => log::debug!(
+            "{} extracted, {} copied, {} generated, {} created, {} skipped, {} warnings, {} errors, {} results",
+            summary.extracted,
+            summary.copied,
+            summary.generated,
+            summary.created,
+            summary.skipped,
+            summary.warnings,
+            summary.errors,
+            summary.results
+        ),
+    }
+    exit_code
+}
+
+/// Prints the log records of leli; those of its dependencies are dropped.
+struct Reporter;
+
+#[derive(Serialize)]
+struct LogLine {
+    event: &'static str,
+    level: String,
+    message: String,
+}
+
+impl Log for Reporter {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("leli") && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match mode() {
+            OutputMode::Json => print_json(&LogLine {
+                event: "log",
+                level: record.level().as_str().to_lowercase(),
+                message: record.args().to_string(),
+            }),
+            OutputMode::Text => match record.level() {
+                Level::Info => write_stdout(format_args!("{}\n", record.args())),
+                Level::Warn | Level::Error => eprintln!("{}", record.args()),
+                Level::Debug | Level::Trace => {
+                    eprintln!("[{}] {}", record.level().as_str().to_lowercase(), record.args())
+                }
+            },
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static REPORTER: Reporter = Reporter;
+
+/// Starts printing progress. `quiet` only lets errors through; `verbose` adds debug (1) and
+/// trace (2) messages.
+pub fn init(output_mode: OutputMode, quiet: bool, verbose: u8) {
+    let _ = MODE.set(output_mode);
+    QUIET.store(quiet, Ordering::Relaxed);
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    if log::set_logger(&REPORTER).is_ok() {
+        log::set_max_level(level);
+    }
+}
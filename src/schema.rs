@@ -1,8 +1,68 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    code_blocks (id) {
+        id -> Nullable<Integer>,
+        document_id -> Integer,
+        language -> Nullable<Text>,
+        target_file -> Nullable<Text>,
+        markdown_start_line -> Integer,
+        markdown_end_line -> Integer,
+        output_start_line -> Nullable<Integer>,
+        output_end_line -> Nullable<Integer>,
+        content_hash -> Text,
+    }
+}
+
+diesel::table! {
+    doc_links (id) {
+        id -> Nullable<Integer>,
+        source_id -> Integer,
+        target_path -> Text,
+        kind -> Text,
+        label -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    doc_revisions (id) {
+        id -> Nullable<Integer>,
+        run_id -> Integer,
+        document_path -> Text,
+        content_hash -> Nullable<Text>,
+        size -> BigInt,
+        generated_at -> Nullable<Timestamp>,
+        code_hash -> Text,
+    }
+}
+
 diesel::table! {
     html_metadata (id) {
         id -> Nullable<Integer>,
         file_path -> Text,
+        source_path -> Nullable<Text>,
+        title -> Nullable<Text>,
+        front_matter -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        generated_at -> Nullable<Timestamp>,
+        leli_version -> Nullable<Text>,
+        service_name -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    save_runs (id) {
+        id -> Nullable<Integer>,
+        started_at -> Timestamp,
+        leli_version -> Nullable<Text>,
+        document_count -> Integer,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(
+    code_blocks,
+    doc_links,
+    doc_revisions,
+    html_metadata,
+    save_runs,
+);
@@ -1,36 +1,50 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use walkdir::WalkDir;
 
+use crate::config::CONFIG_FILE;
+use crate::error::{IoContext, LeliError};
+use crate::report::{self, Event};
+
 pub fn ensure_pandoc_installed() -> bool {
     let output = Command::new("pandoc")
         .arg("--version")
         .output();
 
-    match output {
-        Ok(output) if output.status.success() => true,
-        _ => false,
-    }
+    matches!(output, Ok(output) if output.status.success())
 }
 
-pub fn process_protocol_aimm(app_folder: &Path) -> io::Result<()> {
+pub fn process_protocol_aimm(app_folder: &Path) -> Result<(), LeliError> {
+    if !app_folder.is_dir() {
+        return Err(LeliError::Protocol {
+            path: app_folder.to_path_buf(),
+            message: "the app folder does not exist, extract the code first".to_string(),
+        });
+    }
     let mut folders_to_process = Vec::new();
 
     for entry in WalkDir::new(app_folder).into_iter().filter_map(|e| e.ok()) {
         let path = entry.path();
-        if path.is_dir() {
-            if path.ends_with("private") || path.ends_with("public") {
-                folders_to_process.push(path.to_path_buf());
-            }
+        if path.is_dir() && (path.ends_with("private") || path.ends_with("public")) {
+            folders_to_process.push(path.to_path_buf());
         }
     }
 
     let mut processed_parents = std::collections::HashSet::new();
 
     for folder in folders_to_process {
-        let parent = folder.parent().unwrap().to_path_buf();
+        let Some(parent) = folder.parent().map(Path::to_path_buf) else {
+            continue;
+        };
         if processed_parents.contains(&parent) {
             continue;
         }
@@ -48,14 +62,17 @@ pub fn process_protocol_aimm(app_folder: &Path) -> io::Result<()> {
         }
 
         if !sub_folders.is_empty() {
-            println!("Combining folders into {:?}", src_folder);
-            combine_folders(&sub_folders, &src_folder)?;
+            log::info!("Combining folders into {}", src_folder.display());
+            combine_folders(&sub_folders, &src_folder).map_err(|e| LeliError::Protocol {
+                path: parent.clone(),
+                message: format!("combining into {} failed: {}", src_folder.display(), e),
+            })?;
 
             if private_folder.exists() {
-                fs::remove_dir_all(&private_folder)?;
+                fs::remove_dir_all(&private_folder).at(&private_folder)?;
             }
             if public_folder.exists() {
-                fs::remove_dir_all(&public_folder)?;
+                fs::remove_dir_all(&public_folder).at(&public_folder)?;
             }
 
             processed_parents.insert(parent);
@@ -68,23 +85,373 @@ pub fn process_protocol_aimm(app_folder: &Path) -> io::Result<()> {
 pub fn combine_folders(folders: &[PathBuf], dest_folder: &PathBuf) -> io::Result<()> {
     for folder in folders {
         if folder.exists() && folder.is_dir() {
-            println!("Processing folder: {:?}", folder);
-            for entry in fs::read_dir(&folder)? {
+            log::debug!("Processing folder {}", folder.display());
+            for entry in fs::read_dir(folder)? {
                 let entry = entry?;
                 let entry_path = entry.path();
-                let dest_path = dest_folder.join(entry_path.file_name().unwrap());
+                let dest_path = dest_folder.join(entry.file_name());
 
                 if entry_path.is_dir() {
                     combine_folders(&[entry_path], &dest_path)?;
                 } else {
                     fs::create_dir_all(dest_folder)?;
                     fs::copy(&entry_path, &dest_path)?;
-                    println!("Copied file to {:?}", dest_path);
+                    report::emit(Event::Copied { source: entry_path, target: dest_path });
                 }
             }
         } else {
-            println!("Folder does not exist or is not a directory: {:?}", folder);
+            log::warn!("Folder does not exist or is not a directory: {}", folder.display());
         }
     }
     Ok(())
 }
+
+/// Returns the number of worker threads used when `--jobs` is not given.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Returns the entries of a directory sorted by path, so folder walks are deterministic.
+pub fn sorted_dir_entries(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(folder)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// Runs `job` for every item on up to `jobs` worker threads.
+/// Results are handed to `on_result` on the calling thread in the original item order,
+/// so console output stays the same no matter which worker finishes first.
+pub fn run_parallel<T, R, F, C>(items: Vec<T>, jobs: usize, job: F, mut on_result: C)
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+    C: FnMut(R),
+{
+    let workers = jobs.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let queue = &queue;
+            let job = &job;
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                let Some((index, item)) = next else {
+                    break;
+                };
+                if sender.send((index, job(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Buffer results that finish early until all of their predecessors are done
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next_index) {
+                on_result(result);
+                next_index += 1;
+            }
+        }
+    });
+}
+
+/// Turns the failures collected while processing `total` files into a single error, which
+/// lists all of them when it is reported at the end of the run.
+pub fn report_failures(failures: Vec<(PathBuf, LeliError)>, total: usize) -> Result<(), LeliError> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(LeliError::Failures { total, failures })
+}
+
+/// Name of the gitignore-style files listing what leli should skip.
+pub const IGNORE_FILE: &str = ".leliignore";
+
+/// Files no literate project wants extracted or translated: OS metadata, editor swap and
+/// backup files, dependency and VCS folders. A `!pattern` in `.leliignore` brings them back.
+const DEFAULT_IGNORES: &[&str] = &[
+    ".leliignore", ".DS_Store", "Thumbs.db", "desktop.ini", "*.swp", "*.swo", "*~", ".#*", "node_modules/", ".git/",
+];
+
+/// Selects the files of an input folder by include and exclude globs relative to the folder,
+/// and by the `.leliignore` (and optionally `.gitignore`) files of the folder, its subfolders
+/// and the parents up to the project root.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    root: PathBuf,
+    /// `root` as an absolute path, which the ignore files are matched against
+    absolute_root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    /// Ignore files, the deepest first so they take precedence; the defaults come last
+    ignores: Vec<Gitignore>,
+}
+
+impl FileFilter {
+    /// An empty `include` list includes every file.
+    pub fn new(root: &Path, include: &[String], exclude: &[String], gitignore: bool) -> io::Result<Self> {
+        let absolute_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let names: &[&str] = if gitignore { &[IGNORE_FILE, ".gitignore"] } else { &[IGNORE_FILE] };
+
+        let mut defaults = GitignoreBuilder::new(&absolute_root);
+        for pattern in DEFAULT_IGNORES {
+            defaults.add_line(None, pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        }
+        let defaults = defaults.build().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+        let mut project_files: Vec<PathBuf> = project_folders(&absolute_root)
+            .into_iter()
+            .flat_map(|folder| names.iter().map(move |name| folder.join(name)))
+            .filter(|path| path.is_file())
+            .collect();
+        sort_ignore_files(&mut project_files);
+        let mut ignores = load_ignore_files(&project_files)?;
+        ignores.push(defaults);
+
+        // Folders the root and project ignore files already skip, such as `node_modules` or
+        // ignored output folders, are not searched for further ignore files
+        let mut ignore_files: Vec<PathBuf> = WalkDir::new(&absolute_root)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| !(entry.file_type().is_dir() && first_match_ignores(&ignores, entry.path(), true)))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.depth() > 1 && entry.file_type().is_file())
+            .filter(|entry| names.iter().any(|name| entry.file_name() == *name))
+            .map(|entry| entry.into_path())
+            .collect();
+        sort_ignore_files(&mut ignore_files);
+        ignores.splice(0..0, load_ignore_files(&ignore_files)?);
+
+        Ok(FileFilter {
+            root: root.to_path_buf(),
+            absolute_root,
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+            ignores,
+        })
+    }
+
+    pub fn allows(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let included = self.include.as_ref().is_none_or(|include| include.is_match(relative));
+        let excluded = self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative));
+        included && !excluded && !self.is_ignored(relative, false)
+    }
+
+    /// Whether a folder is ignored as a whole, so walking it can be skipped.
+    pub fn skips_dir(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.is_ignored(relative, true)
+    }
+
+    /// Asks the ignore files from the deepest up; the first one with a matching pattern decides.
+    fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        first_match_ignores(&self.ignores, &self.absolute_root.join(relative), is_dir)
+    }
+}
+
+/// Whether the first of `ignores` with a pattern matching the absolute `path` ignores it.
+fn first_match_ignores(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .filter(|ignore| path.starts_with(ignore.path()))
+        .map(|ignore| ignore.matched_path_or_any_parents(path, is_dir))
+        .find(|matched| !matched.is_none())
+        .is_some_and(|matched| matched.is_ignore())
+}
+
+/// Orders ignore files by precedence: deeper files first; at the same depth `.leliignore`
+/// wins over `.gitignore`.
+fn sort_ignore_files(paths: &mut [PathBuf]) {
+    paths.sort_by_key(|path| {
+        let is_gitignore = path.file_name() != Some(OsStr::new(IGNORE_FILE));
+        (Reverse(path.components().count()), is_gitignore)
+    });
+}
+
+fn load_ignore_files(paths: &[PathBuf]) -> io::Result<Vec<Gitignore>> {
+    paths
+        .iter()
+        .map(|path| match Gitignore::new(path) {
+            (_, Some(e)) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", path.display(), e))),
+            (ignore, None) => Ok(ignore),
+        })
+        .collect()
+}
+
+/// The parents of `folder` whose ignore files apply to it: up to the first folder holding a
+/// `leli.toml` or `.git`, or only `folder` itself outside of a project.
+fn project_folders(folder: &Path) -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    for ancestor in folder.ancestors() {
+        folders.push(ancestor.to_path_buf());
+        if ancestor.join(CONFIG_FILE).is_file() || ancestor.join(".git").exists() {
+            return folders;
+        }
+    }
+    vec![folder.to_path_buf()]
+}
+
+fn build_glob_set(patterns: &[String]) -> io::Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid glob {}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Creates a fresh folder tree from `(path, content)` pairs in the temp folder.
+    fn folder_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("leli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn run_parallel_keeps_item_order() {
+        // The first item only finishes after the second, so results arrive out of order
+        let second_done = AtomicBool::new(false);
+        let finished = Mutex::new(Vec::new());
+        let mut results = Vec::new();
+        run_parallel(
+            (0..8).collect(),
+            2,
+            |item: usize| {
+                if item == 0 {
+                    while !second_done.load(Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                }
+                finished.lock().unwrap().push(item);
+                if item == 1 {
+                    second_done.store(true, Ordering::SeqCst);
+                }
+                item * 10
+            },
+            |result| results.push(result),
+        );
+
+        assert_eq!(finished.into_inner().unwrap()[0], 1);
+        assert_eq!(results, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    #[test]
+    fn run_parallel_handles_more_jobs_than_items() {
+        let mut results = Vec::new();
+        run_parallel(vec!["a", "b"], 16, |item| item.to_uppercase(), |result| results.push(result));
+        assert_eq!(results, vec!["A", "B"]);
+
+        let mut called = false;
+        run_parallel(Vec::<usize>::new(), 4, |item| item, |_| called = true);
+        assert!(!called);
+    }
+
+    #[test]
+    fn deeper_ignore_files_win() {
+        let root = folder_tree(
+            "deeper",
+            &[(".leliignore", "*.md\n"), ("sub/.leliignore", "!keep.md\n"), ("a.md", ""), ("sub/keep.md", ""), ("sub/b.md", "")],
+        );
+        let filter = FileFilter::new(&root, &[], &[], false).unwrap();
+        assert!(!filter.allows(&root.join("a.md")));
+        assert!(filter.allows(&root.join("sub/keep.md")));
+        assert!(!filter.allows(&root.join("sub/b.md")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn leliignore_wins_over_gitignore() {
+        let root = folder_tree(
+            "gitignore",
+            &[(".gitignore", "*.md\n"), (".leliignore", "!a.md\n"), ("a.md", ""), ("b.md", "")],
+        );
+        let filter = FileFilter::new(&root, &[], &[], true).unwrap();
+        assert!(filter.allows(&root.join("a.md")));
+        assert!(!filter.allows(&root.join("b.md")));
+
+        let filter = FileFilter::new(&root, &[], &[], false).unwrap();
+        assert!(filter.allows(&root.join("b.md")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn negated_pattern_includes_a_default_ignore() {
+        let root = folder_tree("defaults", &[(".leliignore", "!*.swp\n"), ("a.swp", ""), (".DS_Store", "")]);
+        let filter = FileFilter::new(&root, &[], &[], false).unwrap();
+        assert!(filter.allows(&root.join("a.swp")));
+        assert!(!filter.allows(&root.join(".DS_Store")));
+        assert!(!filter.allows(&root.join(".leliignore")));
+        assert!(filter.skips_dir(&root.join("node_modules")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parent_ignore_files_apply_up_to_the_project_root() {
+        let root = folder_tree(
+            "parents",
+            &[
+                (".leliignore", "*.md\n"),
+                ("project/leli.toml", ""),
+                ("project/.leliignore", "secret.md\n"),
+                ("project/docs/a.md", ""),
+                ("project/docs/secret.md", ""),
+            ],
+        );
+        let docs = root.join("project/docs");
+        let filter = FileFilter::new(&docs, &[], &[], false).unwrap();
+        assert!(filter.allows(&docs.join("a.md")));
+        assert!(!filter.allows(&docs.join("secret.md")));
+
+        // Outside of a project only the folder's own ignore files apply
+        fs::remove_file(root.join("project/leli.toml")).unwrap();
+        let filter = FileFilter::new(&docs, &[], &[], false).unwrap();
+        assert!(filter.allows(&docs.join("secret.md")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignored_folders_are_not_searched_for_ignore_files() {
+        let root = folder_tree(
+            "prune",
+            &[
+                (".leliignore", "out/\n"),
+                ("out/.leliignore", "!*\n"),
+                ("node_modules/pkg/.leliignore", "!*\n"),
+                ("docs/.leliignore", "draft.md\n"),
+            ],
+        );
+        let filter = FileFilter::new(&root, &[], &[], false).unwrap();
+        // docs, the root and the defaults
+        assert_eq!(filter.ignores.len(), 3);
+        assert!(filter.skips_dir(&root.join("out")));
+        assert!(!filter.allows(&root.join("docs/draft.md")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
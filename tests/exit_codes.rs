@@ -0,0 +1,38 @@
+//! Runs the `leli` binary to check the exit codes of failed commands.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// An empty folder to run `leli` in, so no `leli.toml` is found.
+fn empty_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("leli-exit-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+fn exit_code(folder: &Path, args: &[&str]) -> Option<i32> {
+    let output = Command::new(env!("CARGO_BIN_EXE_leli")).args(args).current_dir(folder).output().unwrap();
+    output.status.code()
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let folder = empty_folder("usage");
+
+    // No --db and no leli.toml to take the database from
+    assert_eq!(exit_code(&folder, &["search", "math"]), Some(2));
+    assert_eq!(exit_code(&folder, &["search", "math-", "--db", "leli.db"]), Some(2));
+
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn database_errors_exit_with_6() {
+    let folder = empty_folder("database");
+
+    assert_eq!(exit_code(&folder, &["query", "--db", "missing/folder/leli.db", "docs"]), Some(6));
+
+    fs::remove_dir_all(&folder).unwrap();
+}